@@ -1,10 +1,23 @@
+use limits::{LimitedReader, Limits};
+use ranges::Ranges;
+use regex::bytes::Regex;
+use std::borrow::Cow;
+use std::collections::VecDeque;
 use std::error::Error;
 use std::fs::File;
-use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
-use std::path::PathBuf;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::num::NonZeroUsize;
+use std::os::fd::AsFd;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use structopt::StructOpt;
 
-type FnResult = Result<(), Box<dyn Error>>;
+mod limits;
+mod parallel;
+mod ranges;
+
+type FnResult<T> = Result<T, Box<dyn Error>>;
+type PrintFn = Box<dyn Fn(&mut dyn BufRead, &mut dyn Write, usize) -> FnResult<u64> + Send + Sync>;
 
 #[derive(Debug, StructOpt)]
 #[structopt(author, about)]
@@ -25,75 +38,506 @@ pub struct Config {
         conflicts_with = "LINES"
     )]
     bytes: Option<usize>,
-    #[structopt(parse(from_os_str), name = "FILE", help = "Input file(s)")]
+    #[structopt(
+        short = "z",
+        long = "zero-terminated",
+        help = "Line delimiter is NUL, not newline",
+        name = "ZERO"
+    )]
+    zero_terminated: bool,
+    #[structopt(
+        long = "exact",
+        help = "Never read stdin past the last line printed, for use with pipes"
+    )]
+    exact: bool,
+    #[structopt(
+        long = "from",
+        help = "Start printing at the first line matching REGEX",
+        name = "FROM_REGEX",
+        conflicts_with = "BYTES"
+    )]
+    from: Option<Regex>,
+    #[structopt(
+        long = "until",
+        help = "Stop printing at the first line matching REGEX",
+        name = "UNTIL_REGEX",
+        conflicts_with = "BYTES"
+    )]
+    until: Option<Regex>,
+    #[structopt(
+        long = "exclusive",
+        help = "Leave out the line matched by --until",
+        requires = "UNTIL_REGEX"
+    )]
+    exclusive: bool,
+    #[structopt(
+        long = "ranges",
+        help = "Print line ranges like 1-5,100-110,-3 in one pass",
+        name = "RANGES",
+        allow_hyphen_values = true,
+        conflicts_with_all = &["LINES", "BYTES", "FROM_REGEX", "UNTIL_REGEX"]
+    )]
+    ranges: Option<Ranges>,
+    #[structopt(
+        long = "number",
+        help = "Number --ranges output and separate ranges with \"--\"",
+        requires = "RANGES"
+    )]
+    number: bool,
+    #[structopt(
+        long = "timeout",
+        help = "Stop reading after DURATION, e.g. 500ms, 30s or 5m, and exit with status 3",
+        name = "DURATION",
+        parse(try_from_str = limits::parse_duration)
+    )]
+    timeout: Option<Duration>,
+    #[structopt(
+        long = "max-bytes",
+        help = "Stop reading after SIZE bytes, e.g. 64K or 10MB, and exit with status 2",
+        name = "SIZE",
+        parse(try_from_str = limits::parse_size)
+    )]
+    max_bytes: Option<u64>,
+    #[structopt(
+        short = "j",
+        long = "jobs",
+        help = "Read up to JOBS files at once, still printing them in order",
+        name = "JOBS",
+        conflicts_with_all = &["DURATION", "SIZE"]
+    )]
+    jobs: Option<NonZeroUsize>,
+    #[structopt(
+        long = "csv",
+        help = "Count CSV records, whose quoted fields may hold newlines, instead of lines",
+        name = "CSV",
+        conflicts_with_all = &["BYTES", "ZERO"]
+    )]
+    csv: bool,
+    #[structopt(
+        long = "keep-header",
+        help = "Print the CSV header row once, then records from each file without banners",
+        requires = "CSV"
+    )]
+    keep_header: bool,
+    #[structopt(
+        short = "q",
+        long = "quiet",
+        visible_alias = "silent",
+        help = "Never print file name headers",
+        overrides_with = "verbose"
+    )]
+    quiet: bool,
+    #[structopt(
+        short = "v",
+        long = "verbose",
+        help = "Always print file name headers",
+        overrides_with = "quiet"
+    )]
+    verbose: bool,
+    #[structopt(
+        parse(from_os_str),
+        name = "FILE",
+        help = "Input file(s), with \"-\" meaning stdin"
+    )]
     files: Vec<PathBuf>,
 }
 
+#[allow(clippy::new_without_default)]
 impl Config {
     pub fn new() -> Self {
-        Config::from_args()
+        let mut config = Config::from_args();
+        if config.files.is_empty() {
+            config.files.push(PathBuf::from("-"));
+        }
+        config
+    }
+
+    fn show_banners(&self) -> bool {
+        // CSV output with a shared header row has to stay valid CSV
+        !self.keep_header && (self.verbose || (self.files.len() > 1 && !self.quiet))
+    }
+
+    fn parallel_jobs(&self) -> Option<usize> {
+        // workers can't share stdin, so more than one "-" stays sequential
+        let stdin_count = self.files.iter().filter(|path| is_stdin(path)).count();
+        match self.jobs {
+            Some(jobs) if jobs.get() > 1 && stdin_count <= 1 => Some(jobs.get()),
+            _ => None,
+        }
     }
 }
 
-pub fn run(config: &Config) -> FnResult {
-    let (print_to_stream, limit): (
-        Box<dyn Fn(&mut dyn BufRead, &mut dyn Write, usize) -> FnResult>,
-        usize,
-    ) = match config.bytes {
-        Some(bytes) => (Box::new(print_bytes), bytes),
-        _ => match config.lines {
-            Some(lines) => (Box::new(print_lines), lines),
-            _ => (Box::new(print_lines), 10),
-        },
-    };
+/// Decides which lines of a stream get printed.
+struct LineFilter {
+    delimiter: u8,
+    csv: bool,
+    from: Option<Regex>,
+    until: Option<Regex>,
+    exclusive: bool,
+}
+
+impl LineFilter {
+    fn new(config: &Config) -> Self {
+        LineFilter {
+            delimiter: if config.zero_terminated { b'\0' } else { b'\n' },
+            csv: config.csv,
+            from: config.from.clone(),
+            until: config.until.clone(),
+            exclusive: config.exclusive,
+        }
+    }
+
+    fn has_patterns(&self) -> bool {
+        self.from.is_some() || self.until.is_some()
+    }
+
+    /// Reads one line, or with --csv one whole record, delimiter included.
+    fn read_record(&self, input: &mut dyn BufRead, buf: &mut Vec<u8>) -> io::Result<usize> {
+        if self.csv {
+            read_csv_record(input, buf)
+        } else {
+            input.read_until(self.delimiter, buf)
+        }
+    }
+}
+
+/// Reads one RFC 4180 record, which ends at the first newline outside quotes.
+fn read_csv_record(input: &mut dyn BufRead, buf: &mut Vec<u8>) -> io::Result<usize> {
+    // an escaped "" quote flips the state twice, so counting quotes is enough
+    let start = buf.len();
+    let mut in_quotes = false;
+    loop {
+        let line_start = buf.len();
+        if input.read_until(b'\n', buf)? == 0 {
+            break;
+        }
+        let quotes = buf[line_start..].iter().filter(|&&b| b == b'"').count();
+        in_quotes ^= quotes % 2 == 1;
+        if !in_quotes {
+            break;
+        }
+    }
+    Ok(buf.len() - start)
+}
+
+/// How much of each input gets printed, shared by every input of a run.
+struct Head {
+    print_to_stream: PrintFn,
+    limit: usize,
+    keep_header: bool,
+}
+
+impl Head {
+    fn new(config: &Config) -> Self {
+        let filter = LineFilter::new(config);
+        // with a pattern to stop at, -n is only an upper bound
+        let default_lines = if filter.has_patterns() {
+            usize::MAX
+        } else {
+            10
+        };
+
+        let (print_to_stream, limit): (PrintFn, usize) = match (&config.ranges, config.bytes) {
+            (Some(ranges), _) => {
+                let (ranges, number) = (ranges.clone(), config.number);
+                let print_ranges = move |input: &mut dyn BufRead, output: &mut dyn Write, _| {
+                    print_ranges(input, output, &ranges, &filter, number)
+                };
+                (Box::new(print_ranges), 0)
+            }
+            (_, Some(bytes)) => (Box::new(print_bytes), bytes),
+            _ => {
+                let print_lines =
+                    move |input: &mut dyn BufRead, output: &mut dyn Write, num_lines| {
+                        print_lines(input, output, num_lines, &filter)
+                    };
+                (Box::new(print_lines), config.lines.unwrap_or(default_lines))
+            }
+        };
+
+        Head {
+            print_to_stream,
+            limit,
+            keep_header: config.keep_header,
+        }
+    }
+
+    /// With --keep-header, takes the CSV header row off the front of an
+    /// input. An empty input has none, so the next file's gets printed.
+    fn read_header(&self, input: &mut dyn BufRead) -> io::Result<Option<Vec<u8>>> {
+        if !self.keep_header {
+            return Ok(None);
+        }
+        let mut header = Vec::new();
+        read_csv_record(input, &mut header)?;
+        Ok(Some(header).filter(|header| !header.is_empty()))
+    }
+
+    /// Prints the head of one input, returning how many bytes of it were used.
+    fn print(&self, input: &mut dyn BufRead, output: &mut dyn Write) -> FnResult<u64> {
+        (self.print_to_stream)(input, output, self.limit)
+    }
+}
 
+/// Runs rhead, returning the exit status: 0 normally, 1 if any input could
+/// not be opened or read, or the status of whichever of --max-bytes and
+/// --timeout ended the run.
+pub fn run(config: &Config) -> FnResult<i32> {
+    let head = Head::new(config);
+    let limits = Limits::new(config.max_bytes, config.timeout);
     let mut write_stream = BufWriter::new(io::stdout());
 
-    if config.files.len() == 0 {
-        // read stdin
-        let mut read_stream = BufReader::new(io::stdin());
-        print_to_stream(&mut read_stream, &mut write_stream, limit)?;
+    let all_ok = if let Some(jobs) = config.parallel_jobs() {
+        // read inputs on worker threads
+        parallel::print_files(config, &head, jobs, &mut write_stream)?
     } else {
-        // read files
-        for (index, filepath) in config.files.iter().enumerate() {
-            let file = match File::open(filepath) {
-                Ok(file) => file,
+        // read inputs
+        let mut all_ok = true;
+        let mut header_printed = false;
+        for index in 0..config.files.len() {
+            let mut input = match Input::open(config, index, &limits) {
+                Ok(input) => input,
                 Err(e) => {
-                    eprintln!("rhead: {}: {}", filepath.to_str().unwrap(), e);
+                    eprintln!("{}", file_error(&config.files[index], e));
+                    all_ok = false;
                     continue;
                 }
             };
 
-            let mut read_stream = BufReader::new(file);
-
-            if config.files.len() > 1 {
-                // write header
-                if index > 0 {
-                    writeln!(write_stream)?;
+            let result = input
+                .read_header(&head)
+                .map_err(Into::into)
+                .and_then(|header| {
+                    if let Some(header) = header {
+                        // every file carries the header, but it is only printed once
+                        if !header_printed {
+                            write_stream.write_all(&header)?;
+                            header_printed = true;
+                        }
+                    }
+                    input.print(config, &head, index, &mut write_stream)
+                });
+            if let Err(e) = result {
+                // a file that can't be read is skipped, as in GNU head, but
+                // failing to write ends the run
+                if !input.read_failed() {
+                    return Err(e);
                 }
-                writeln!(write_stream, "==> {} <==", filepath.to_str().unwrap())?;
+                write_stream.flush()?;
+                eprintln!("{}", file_error(&config.files[index], e));
+                all_ok = false;
+            }
+            if limits.hit().is_some() {
+                break;
             }
+        }
+        all_ok
+    };
 
-            print_to_stream(&mut read_stream, &mut write_stream, limit)?;
+    write_stream.flush()?;
+    Ok(match limits.hit() {
+        Some(hit) => hit.exit_code(),
+        None if !all_ok => 1,
+        None => 0,
+    })
+}
+
+fn is_stdin(filepath: &Path) -> bool {
+    filepath == Path::new("-")
+}
+
+fn display_name(filepath: &Path) -> Cow<'_, str> {
+    if is_stdin(filepath) {
+        Cow::from("standard input")
+    } else {
+        filepath.to_string_lossy()
+    }
+}
+
+fn file_error(filepath: &Path, e: impl std::fmt::Display) -> String {
+    format!("rhead: {}: {}", display_name(filepath), e)
+}
+
+/// One opened input, which hands back unused bytes if it is stdin.
+struct Input<'a> {
+    read_stream: BufReader<LimitedReader<'a, File>>,
+    // where stdin started, if it is seekable
+    start: Option<u64>,
+    consumed: u64,
+}
+
+impl<'a> Input<'a> {
+    fn open(config: &Config, index: usize, limits: &'a Limits) -> io::Result<Self> {
+        let filepath = &config.files[index];
+        if !is_stdin(filepath) {
+            let file = File::open(filepath)?;
+            return Ok(Input {
+                read_stream: BufReader::new(LimitedReader::new(file, limits)),
+                start: None,
+                consumed: 0,
+            });
         }
+
+        // the duplicated descriptor shares its file offset with stdin, so
+        // seeking it is visible to whoever reads stdin after we exit
+        let mut stdin = File::from(io::stdin().as_fd().try_clone_to_owned()?);
+        let start = stdin.stream_position().ok();
+
+        // a one byte buffer makes read_until stop right after the delimiter,
+        // so nothing past the last line is taken from a pipe
+        let capacity = if config.exact { 1 } else { 8 * 1024 };
+        Ok(Input {
+            read_stream: BufReader::with_capacity(capacity, LimitedReader::new(stdin, limits)),
+            start,
+            consumed: 0,
+        })
     }
 
-    write_stream.flush()?;
-    Ok(())
+    /// With --keep-header, takes the CSV header row off the front of the input.
+    fn read_header(&mut self, head: &Head) -> io::Result<Option<Vec<u8>>> {
+        let header = head.read_header(&mut self.read_stream)?;
+        if let Some(header) = &header {
+            self.consumed += header.len() as u64;
+        }
+        Ok(header)
+    }
+
+    /// Whether reading the input failed, as opposed to writing it out.
+    fn read_failed(&self) -> bool {
+        self.read_stream.get_ref().failed()
+    }
+
+    fn print(
+        &mut self,
+        config: &Config,
+        head: &Head,
+        index: usize,
+        output: &mut dyn Write,
+    ) -> FnResult<()> {
+        if config.show_banners() {
+            // write header
+            if index > 0 {
+                writeln!(output)?;
+            }
+            writeln!(output, "==> {} <==", display_name(&config.files[index]))?;
+        }
+
+        self.consumed += head.print(&mut self.read_stream, output)?;
+
+        // give back whatever was buffered but not printed, like GNU head
+        if let Some(start) = self.start {
+            let mut stdin = self.read_stream.get_ref().get_ref();
+            stdin.seek(SeekFrom::Start(start + self.consumed))?;
+        }
+        Ok(())
+    }
 }
 
-fn print_bytes(input: &mut dyn BufRead, output: &mut dyn Write, num_bytes: usize) -> FnResult {
-    for byte in input.bytes().take(num_bytes) {
-        let byte = byte?;
-        write!(output, "{}", byte as char)?;
+fn print_bytes(input: &mut dyn BufRead, output: &mut dyn Write, num_bytes: usize) -> FnResult<u64> {
+    Ok(io::copy(&mut input.take(num_bytes as u64), output)?)
+}
+
+fn print_lines(
+    input: &mut dyn BufRead,
+    output: &mut dyn Write,
+    num_lines: usize,
+    filter: &LineFilter,
+) -> FnResult<u64> {
+    // lines are copied as raw bytes, terminator included, so CRLF endings,
+    // a missing final newline and invalid UTF-8 all pass through untouched
+    let mut buf = Vec::new();
+    let mut consumed = 0;
+    let mut printed = 0;
+    let mut started = filter.from.is_none();
+    while printed < num_lines {
+        buf.clear();
+        let len = filter.read_record(input, &mut buf)?;
+        if len == 0 {
+            break;
+        }
+
+        let line = buf.strip_suffix(&[filter.delimiter]).unwrap_or(&buf);
+        if !started {
+            started = filter.from.as_ref().unwrap().is_match(line);
+            if !started {
+                consumed += len as u64;
+                continue;
+            }
+        }
+        if let Some(until) = &filter.until {
+            if until.is_match(line) {
+                if !filter.exclusive {
+                    output.write_all(&buf)?;
+                    consumed += len as u64;
+                }
+                break;
+            }
+        }
+
+        output.write_all(&buf)?;
+        consumed += len as u64;
+        printed += 1;
     }
-    Ok(())
+    Ok(consumed)
 }
 
-fn print_lines(input: &mut dyn BufRead, output: &mut dyn Write, num_lines: usize) -> FnResult {
-    for line in input.lines().take(num_lines) {
-        let line = line?;
-        writeln!(output, "{}", line)?;
+fn print_ranges(
+    input: &mut dyn BufRead,
+    output: &mut dyn Write,
+    ranges: &Ranges,
+    filter: &LineFilter,
+    number: bool,
+) -> FnResult<u64> {
+    // lines that may still turn out to be among the last few are held back
+    // with a flag saying whether a positive range wants them anyway, which
+    // keeps the output in input order without reading the input twice
+    let tail_len = ranges.tail_len();
+    let mut pending: VecDeque<(usize, bool, Vec<u8>)> = VecDeque::with_capacity(tail_len + 1);
+    let mut last_printed = None;
+    let mut write_line = |line_no: usize, line: &[u8]| -> io::Result<()> {
+        if number {
+            if matches!(last_printed, Some(prev) if prev + 1 != line_no) {
+                output.write_all(b"--")?;
+                output.write_all(&[filter.delimiter])?;
+            }
+            write!(output, "{:width$}\t", line_no, width = 6)?;
+        }
+        last_printed = Some(line_no);
+        output.write_all(line)
+    };
+
+    let last_line = ranges.last_line();
+    let mut buf = Vec::new();
+    let mut consumed = 0;
+    let mut line_no = 0;
+    while last_line.is_none_or(|last| line_no < last) {
+        buf.clear();
+        let len = filter.read_record(input, &mut buf)?;
+        if len == 0 {
+            break;
+        }
+        consumed += len as u64;
+        line_no += 1;
+
+        let selected = ranges.selects(line_no);
+        if tail_len == 0 {
+            if selected {
+                write_line(line_no, &buf)?;
+            }
+            continue;
+        }
+
+        pending.push_back((line_no, selected, buf.clone()));
+        if pending.len() > tail_len {
+            if let Some((line_no, true, line)) = pending.pop_front() {
+                write_line(line_no, &line)?;
+            }
+        }
+    }
+
+    // whatever is still held back is the tail of the input
+    for (line_no, _, line) in pending {
+        write_line(line_no, &line)?;
     }
-    Ok(())
+    Ok(consumed)
 }
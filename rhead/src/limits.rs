@@ -0,0 +1,211 @@
+use std::cell::Cell;
+use std::io::{self, Read};
+use std::mem;
+use std::os::fd::{AsFd, AsRawFd};
+use std::time::{Duration, Instant};
+
+/// The run-wide limit that ended reading early.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LimitHit {
+    MaxBytes,
+    Timeout,
+}
+
+impl LimitHit {
+    pub fn exit_code(self) -> i32 {
+        match self {
+            LimitHit::MaxBytes => 2,
+            LimitHit::Timeout => 3,
+        }
+    }
+}
+
+/// Byte and time budgets shared by every input of a run.
+#[derive(Debug)]
+pub struct Limits {
+    bytes_left: Cell<Option<u64>>,
+    deadline: Option<Instant>,
+    hit: Cell<Option<LimitHit>>,
+}
+
+impl Limits {
+    pub fn new(max_bytes: Option<u64>, timeout: Option<Duration>) -> Self {
+        Limits {
+            bytes_left: Cell::new(max_bytes),
+            deadline: timeout.map(|timeout| Instant::now() + timeout),
+            hit: Cell::new(None),
+        }
+    }
+
+    pub fn hit(&self) -> Option<LimitHit> {
+        self.hit.get()
+    }
+}
+
+/// Ends the stream early, as if at end of file, once a limit is hit.
+pub struct LimitedReader<'a, R> {
+    inner: R,
+    limits: &'a Limits,
+    // whether reading failed, as opposed to writing what was read
+    failed: bool,
+}
+
+impl<'a, R: Read + AsFd> LimitedReader<'a, R> {
+    pub fn new(inner: R, limits: &'a Limits) -> Self {
+        LimitedReader {
+            inner,
+            limits,
+            failed: false,
+        }
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Whether a read from the inner stream has failed.
+    pub fn failed(&self) -> bool {
+        self.failed
+    }
+
+    // waits for input to arrive, returning false if the deadline passes first
+    fn wait_readable(&self, deadline: Instant) -> io::Result<bool> {
+        let mut poll_fd = libc::pollfd {
+            fd: self.inner.as_fd().as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            // round up so we never wake just short of the deadline
+            let millis = left.as_micros().div_ceil(1000).min(i32::MAX as u128) as i32;
+            match unsafe { libc::poll(&mut poll_fd, 1, millis) } {
+                -1 => {
+                    let e = io::Error::last_os_error();
+                    if e.kind() != io::ErrorKind::Interrupted {
+                        return Err(e);
+                    }
+                }
+                0 => return Ok(false),
+                _ => return Ok(true),
+            }
+        }
+    }
+
+    // whether there is more to read, found out without reading any of it so
+    // none is lost to whoever reads stdin after us: a regular file by its
+    // size, anything else by whether it has already hung up with nothing
+    // left in it, a stream still open counting as having more
+    fn input_left(&self) -> io::Result<bool> {
+        let fd = self.inner.as_fd().as_raw_fd();
+        let mut stat: libc::stat = unsafe { mem::zeroed() };
+        if unsafe { libc::fstat(fd, &mut stat) } == 0
+            && stat.st_mode & libc::S_IFMT == libc::S_IFREG
+        {
+            let position = unsafe { libc::lseek(fd, 0, libc::SEEK_CUR) };
+            if position >= 0 {
+                return Ok(position < stat.st_size);
+            }
+        }
+
+        let mut poll_fd = libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        while unsafe { libc::poll(&mut poll_fd, 1, 0) } == -1 {
+            let e = io::Error::last_os_error();
+            if e.kind() != io::ErrorKind::Interrupted {
+                return Err(e);
+            }
+        }
+        Ok(poll_fd.revents & libc::POLLIN != 0 || poll_fd.revents & libc::POLLHUP == 0)
+    }
+
+    fn read_limited(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() || self.limits.hit().is_some() {
+            return Ok(0);
+        }
+
+        let mut len = buf.len();
+        let budget_spent = match self.limits.bytes_left.get() {
+            Some(0) => true,
+            Some(bytes_left) => {
+                len = len.min(bytes_left.try_into().unwrap_or(usize::MAX));
+                false
+            }
+            None => false,
+        };
+
+        if let Some(deadline) = self.limits.deadline {
+            if !self.wait_readable(deadline)? {
+                self.limits.hit.set(Some(LimitHit::Timeout));
+                return Ok(0);
+            }
+        }
+
+        if budget_spent {
+            // the budget only cut the run short if there was more to read
+            if self.input_left()? {
+                self.limits.hit.set(Some(LimitHit::MaxBytes));
+            }
+            return Ok(0);
+        }
+
+        let read = self.inner.read(&mut buf[..len])?;
+        if let Some(bytes_left) = self.limits.bytes_left.get() {
+            self.limits.bytes_left.set(Some(bytes_left - read as u64));
+        }
+        Ok(read)
+    }
+}
+
+impl<R: Read + AsFd> Read for LimitedReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let result = self.read_limited(buf);
+        if matches!(&result, Err(e) if e.kind() != io::ErrorKind::Interrupted) {
+            self.failed = true;
+        }
+        result
+    }
+}
+
+/// Parses a duration such as `1.5`, `500ms`, `30s`, `5m` or `1h`; bare numbers are seconds.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let split = s.find(|c: char| c.is_ascii_alphabetic()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let scale = match unit {
+        "ms" => 0.001,
+        "" | "s" => 1.0,
+        "m" => 60.0,
+        "h" => 60.0 * 60.0,
+        _ => return Err(format!("invalid duration unit '{}'", unit)),
+    };
+    match number.parse::<f64>() {
+        Ok(number) if number >= 0.0 => Duration::try_from_secs_f64(number * scale)
+            .map_err(|_| format!("duration '{}' is out of range", s)),
+        _ => Err(format!("invalid duration '{}'", s)),
+    }
+}
+
+/// Parses a size such as `512`, `64K` or `10MB`; K, M and G are powers of
+/// 1024 and KB, MB and GB are powers of 1000, as in GNU head.
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let scale: u64 = match unit {
+        "" => 1,
+        "K" | "KiB" => 1 << 10,
+        "M" | "MiB" => 1 << 20,
+        "G" | "GiB" => 1 << 30,
+        "KB" => 1_000,
+        "MB" => 1_000_000,
+        "GB" => 1_000_000_000,
+        _ => return Err(format!("invalid size unit '{}'", unit)),
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(scale))
+        .ok_or_else(|| format!("invalid size '{}'", s))
+}
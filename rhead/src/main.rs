@@ -2,8 +2,11 @@ use rhead::Config;
 use std::process;
 
 fn main() {
-    if let Err(e) = rhead::run(&Config::new()) {
-        eprintln!("{}", e);
-        process::exit(1);
+    match rhead::run(&Config::new()) {
+        Ok(code) => process::exit(code),
+        Err(e) => {
+            eprintln!("rhead: {}", e);
+            process::exit(1);
+        }
     }
 }
@@ -0,0 +1,160 @@
+use crate::limits::Limits;
+use crate::{file_error, Config, FnResult, Head, Input};
+use std::io::{self, Write};
+use std::mem;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::Mutex;
+use std::thread;
+
+// each worker holds at most QUEUE_DEPTH full chunks plus the one it is filling
+const CHUNK_SIZE: usize = 64 * 1024;
+const QUEUE_DEPTH: usize = 4;
+
+/// A piece of one file's output, passed from a worker to the printing thread.
+enum Chunk {
+    Data(Vec<u8>),
+    // a file's CSV header row, printed only for the first file that has one
+    Header(Vec<u8>),
+    // a file that could not be opened or read, reported on stderr
+    FileError(String),
+    // an error that ends the whole run, as it would sequentially
+    Fatal(String),
+}
+
+/// Buffers output into chunks and hands them to the printing thread.
+struct ChunkWriter {
+    sender: SyncSender<Chunk>,
+    buf: Vec<u8>,
+}
+
+impl ChunkWriter {
+    fn send(&self, chunk: Chunk) -> io::Result<()> {
+        // the printing thread only hangs up once it has stopped for good
+        self.sender
+            .send(chunk)
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))
+    }
+}
+
+impl Write for ChunkWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        if self.buf.len() >= CHUNK_SIZE {
+            self.flush()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.buf.is_empty() {
+            let chunk = mem::replace(&mut self.buf, Vec::with_capacity(CHUNK_SIZE));
+            self.send(Chunk::Data(chunk))?;
+        }
+        Ok(())
+    }
+}
+
+/// Prints the head of every file, reading up to `jobs` files at once, and
+/// returns whether all of them could be opened and read.
+///
+/// Files are handed to workers in argument order and each gets its own
+/// bounded channel, so the printing thread can drain them one after another
+/// and the output matches the sequential path exactly.
+pub fn print_files(
+    config: &Config,
+    head: &Head,
+    jobs: usize,
+    output: &mut dyn Write,
+) -> FnResult<bool> {
+    let (senders, receivers): (Vec<_>, Vec<_>) = config
+        .files
+        .iter()
+        .map(|_| mpsc::sync_channel(QUEUE_DEPTH))
+        .unzip();
+    let queue = Mutex::new(senders.into_iter().enumerate());
+
+    thread::scope(|scope| {
+        for _ in 0..jobs.min(config.files.len()) {
+            scope.spawn(|| loop {
+                let next = queue.lock().unwrap().next();
+                let Some((index, sender)) = next else {
+                    break;
+                };
+                let mut writer = ChunkWriter {
+                    sender,
+                    buf: Vec::with_capacity(CHUNK_SIZE),
+                };
+                if head_file(config, head, index, &mut writer).is_err() {
+                    break;
+                }
+            });
+        }
+
+        // dropping the receivers on an early return stops the workers
+        print_chunks(receivers, output)
+    })
+}
+
+// Reads one file into the writer, returning Err only once the printing
+// thread has stopped listening.
+fn head_file(
+    config: &Config,
+    head: &Head,
+    index: usize,
+    writer: &mut ChunkWriter,
+) -> io::Result<()> {
+    let limits = Limits::new(None, None);
+    let mut input = match Input::open(config, index, &limits) {
+        Ok(input) => input,
+        Err(e) => {
+            let message = file_error(&config.files[index], e);
+            return writer.send(Chunk::FileError(message));
+        }
+    };
+
+    let result = match input.read_header(head) {
+        Ok(header) => {
+            if let Some(header) = header {
+                writer.send(Chunk::Header(header))?;
+            }
+            input.print(config, head, index, writer)
+        }
+        Err(e) => Err(e.into()),
+    };
+    writer.flush()?;
+    if let Err(e) = result {
+        if input.read_failed() {
+            writer.send(Chunk::FileError(file_error(&config.files[index], e)))?;
+        } else {
+            writer.send(Chunk::Fatal(e.to_string()))?;
+        }
+    }
+    Ok(())
+}
+
+fn print_chunks(receivers: Vec<Receiver<Chunk>>, output: &mut dyn Write) -> FnResult<bool> {
+    let mut all_ok = true;
+    let mut header_printed = false;
+    for receiver in receivers {
+        // the channel closes once the worker is done with the file
+        for chunk in receiver {
+            match chunk {
+                Chunk::Data(data) => output.write_all(&data)?,
+                Chunk::Header(header) => {
+                    if !header_printed {
+                        output.write_all(&header)?;
+                        header_printed = true;
+                    }
+                }
+                Chunk::FileError(message) => {
+                    // whatever the file printed before failing goes first
+                    output.flush()?;
+                    eprintln!("{}", message);
+                    all_ok = false;
+                }
+                Chunk::Fatal(message) => return Err(message.into()),
+            }
+        }
+    }
+    Ok(all_ok)
+}
@@ -0,0 +1,78 @@
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq)]
+enum LineRange {
+    // lines start..=end counted from 1, open ended when end is None
+    Span(usize, Option<usize>),
+    // the last n lines of input
+    Last(usize),
+}
+
+/// A comma separated list of line ranges, e.g. `1-5,100-110,-3`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ranges(Vec<LineRange>);
+
+impl Ranges {
+    /// Whether a (1-based) line number falls in any positive range.
+    pub fn selects(&self, line: usize) -> bool {
+        self.0.iter().any(|range| match *range {
+            LineRange::Span(start, end) => start <= line && end.is_none_or(|end| line <= end),
+            LineRange::Last(_) => false,
+        })
+    }
+
+    /// How many trailing lines must be held back until the end of input.
+    pub fn tail_len(&self) -> usize {
+        self.0
+            .iter()
+            .map(|range| match *range {
+                LineRange::Last(count) => count,
+                LineRange::Span(..) => 0,
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// The last line worth reading, or None if input must be read to the end.
+    pub fn last_line(&self) -> Option<usize> {
+        let mut last = 0;
+        for range in &self.0 {
+            match *range {
+                LineRange::Span(_, Some(end)) => last = last.max(end),
+                _ => return None,
+            }
+        }
+        Some(last)
+    }
+}
+
+impl FromStr for Ranges {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .map(parse_range)
+            .collect::<Result<_, _>>()
+            .map(Ranges)
+    }
+}
+
+fn parse_range(s: &str) -> Result<LineRange, String> {
+    let parse_line = |n: &str| match n.parse::<usize>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!("invalid line number '{}' in range '{}'", n, s)),
+    };
+
+    match s.split_once('-') {
+        None => parse_line(s).map(|line| LineRange::Span(line, Some(line))),
+        Some(("", count)) => parse_line(count).map(LineRange::Last),
+        Some((start, "")) => parse_line(start).map(|start| LineRange::Span(start, None)),
+        Some((start, end)) => {
+            let (start, end) = (parse_line(start)?, parse_line(end)?);
+            if start > end {
+                return Err(format!("range '{}' ends before it starts", s));
+            }
+            Ok(LineRange::Span(start, Some(end)))
+        }
+    }
+}
@@ -1,6 +1,11 @@
+use assert_cmd::assert::OutputAssertExt;
+use assert_cmd::cargo::cargo_bin;
 use assert_cmd::Command;
+use assert_fs::prelude::*;
 use predicates::prelude::*;
 use std::error::Error;
+use std::process::Stdio;
+use std::time::{Duration, Instant};
 
 type TestResult = Result<(), Box<dyn Error>>;
 
@@ -138,3 +143,664 @@ fn unknown_flag_shows_usage() -> TestResult {
 
     Ok(())
 }
+
+#[test]
+fn preserves_crlf_line_endings() -> TestResult {
+    let mut cmd = Command::cargo_bin("rhead")?;
+    cmd.arg("-n")
+        .arg("2")
+        .write_stdin("one\r\ntwo\r\nthree\r\n")
+        .assert()
+        .code(0)
+        .stdout("one\r\ntwo\r\n");
+
+    Ok(())
+}
+
+#[test]
+fn does_not_add_missing_final_newline() -> TestResult {
+    let mut cmd = Command::cargo_bin("rhead")?;
+    cmd.write_stdin("one\ntwo")
+        .assert()
+        .code(0)
+        .stdout("one\ntwo");
+
+    Ok(())
+}
+
+#[test]
+fn passes_through_invalid_utf8() -> TestResult {
+    let input: &[u8] = b"caf\xe9\n\xff\xfe\nthird\n";
+
+    let mut cmd = Command::cargo_bin("rhead")?;
+    cmd.arg("-n")
+        .arg("2")
+        .write_stdin(input)
+        .assert()
+        .code(0)
+        .stdout(&input[..8]);
+
+    let mut cmd = Command::cargo_bin("rhead")?;
+    cmd.arg("-c")
+        .arg("4")
+        .write_stdin(input)
+        .assert()
+        .code(0)
+        .stdout(&input[..4]);
+
+    Ok(())
+}
+
+#[test]
+fn z_flag_splits_on_nul() -> TestResult {
+    let mut cmd = Command::cargo_bin("rhead")?;
+    cmd.arg("-z")
+        .arg("-n")
+        .arg("2")
+        .write_stdin("a\nb\0c\0d\0")
+        .assert()
+        .code(0)
+        .stdout("a\nb\0c\0");
+
+    Ok(())
+}
+
+fn numbered_lines(count: usize) -> String {
+    (1..=count).map(|n| format!("line {}\n", n)).collect()
+}
+
+#[test]
+fn leaves_rest_of_seekable_stdin_unread() -> TestResult {
+    let input = numbered_lines(5000);
+    let file = assert_fs::NamedTempFile::new("data.txt")?;
+    file.write_str(&input)?;
+
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c")
+        .arg(format!(
+            "({} -n 1; cat) < {}",
+            cargo_bin("rhead").display(),
+            file.path().display()
+        ))
+        .assert()
+        .code(0)
+        .stdout(input);
+
+    Ok(())
+}
+
+#[test]
+fn exact_flag_leaves_rest_of_pipe_unread() -> TestResult {
+    let input = numbered_lines(5000);
+
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c")
+        .arg(format!(
+            "({} --exact -n 3; cat)",
+            cargo_bin("rhead").display()
+        ))
+        .write_stdin(input.clone())
+        .assert()
+        .code(0)
+        .stdout(input);
+
+    Ok(())
+}
+
+#[test]
+fn until_flag_stops_at_match() -> TestResult {
+    let mut cmd = Command::cargo_bin("rhead")?;
+    cmd.arg("--until")
+        .arg("^Started")
+        .write_stdin(numbered_lines(20) + "Started server\nrequest 1\n")
+        .assert()
+        .code(0)
+        .stdout(numbered_lines(20) + "Started server\n");
+
+    Ok(())
+}
+
+#[test]
+fn exclusive_flag_leaves_out_until_match() -> TestResult {
+    let mut cmd = Command::cargo_bin("rhead")?;
+    cmd.arg("--until")
+        .arg("3$")
+        .arg("--exclusive")
+        .write_stdin(numbered_lines(5))
+        .assert()
+        .code(0)
+        .stdout(numbered_lines(2));
+
+    Ok(())
+}
+
+#[test]
+fn from_flag_starts_at_match() -> TestResult {
+    let mut cmd = Command::cargo_bin("rhead")?;
+    cmd.arg("--from")
+        .arg("line 3")
+        .arg("--until")
+        .arg("line 5")
+        .write_stdin(numbered_lines(8))
+        .assert()
+        .code(0)
+        .stdout("line 3\nline 4\nline 5\n");
+
+    Ok(())
+}
+
+#[test]
+fn n_flag_bounds_pattern_output() -> TestResult {
+    let mut cmd = Command::cargo_bin("rhead")?;
+    cmd.arg("--from")
+        .arg("line 2$")
+        .arg("-n")
+        .arg("2")
+        .write_stdin(numbered_lines(8))
+        .assert()
+        .code(0)
+        .stdout("line 2\nline 3\n");
+
+    Ok(())
+}
+
+#[test]
+fn exclusive_flag_requires_until() -> TestResult {
+    let mut cmd = Command::cargo_bin("rhead")?;
+    cmd.arg("--exclusive")
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("USAGE"));
+
+    Ok(())
+}
+
+#[test]
+fn ranges_flag_prints_each_range_in_order() -> TestResult {
+    let mut cmd = Command::cargo_bin("rhead")?;
+    cmd.arg("--ranges")
+        .arg("7-8,2,-2")
+        .write_stdin(numbered_lines(20))
+        .assert()
+        .code(0)
+        .stdout("line 2\nline 7\nline 8\nline 19\nline 20\n");
+
+    Ok(())
+}
+
+#[test]
+fn ranges_flag_prints_overlapping_lines_once() -> TestResult {
+    // lines 6 and 7 are only known to be in the tail once input ends, but
+    // still come out ahead of line 8
+    let mut cmd = Command::cargo_bin("rhead")?;
+    cmd.arg("--ranges")
+        .arg("1-3,2-5,8-9,-5")
+        .write_stdin(numbered_lines(10))
+        .assert()
+        .code(0)
+        .stdout(numbered_lines(10));
+
+    Ok(())
+}
+
+#[test]
+fn ranges_flag_takes_list_starting_with_tail() -> TestResult {
+    let mut cmd = Command::cargo_bin("rhead")?;
+    cmd.arg("--ranges")
+        .arg("-3")
+        .write_stdin(numbered_lines(10))
+        .assert()
+        .code(0)
+        .stdout("line 8\nline 9\nline 10\n");
+
+    let mut cmd = Command::cargo_bin("rhead")?;
+    cmd.args(["--ranges", "-2,1"])
+        .write_stdin(numbered_lines(10))
+        .assert()
+        .code(0)
+        .stdout("line 1\nline 9\nline 10\n");
+
+    Ok(())
+}
+
+#[test]
+fn number_flag_shows_line_numbers_and_separators() -> TestResult {
+    let mut cmd = Command::cargo_bin("rhead")?;
+    cmd.arg("--ranges")
+        .arg("1-2,4,9-")
+        .arg("--number")
+        .write_stdin(numbered_lines(10))
+        .assert()
+        .code(0)
+        .stdout(
+            "     1\tline 1\n     2\tline 2\n--\n     4\tline 4\n--\n     9\tline 9\n    10\tline 10\n",
+        );
+
+    Ok(())
+}
+
+#[test]
+fn ranges_flag_leaves_rest_of_stdin_unread() -> TestResult {
+    let input = numbered_lines(5000);
+
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c")
+        .arg(format!(
+            "({} --exact --ranges 2-3; cat)",
+            cargo_bin("rhead").display()
+        ))
+        .write_stdin(input.clone())
+        .assert()
+        .code(0)
+        .stdout(input.replacen("line 1\n", "", 1));
+
+    Ok(())
+}
+
+#[test]
+fn ranges_flag_rejects_bad_ranges() -> TestResult {
+    for bad in ["0", "5-1", "a-b", "1,,2", "-0"] {
+        let mut cmd = Command::cargo_bin("rhead")?;
+        cmd.arg("--ranges")
+            .arg(bad)
+            .assert()
+            .code(1)
+            .stderr(predicate::str::contains("--ranges"));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn ranges_flag_conflicts_with_n_flag() -> TestResult {
+    let mut cmd = Command::cargo_bin("rhead")?;
+    cmd.arg("--ranges")
+        .arg("1-2")
+        .arg("-n")
+        .arg("3")
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("USAGE"));
+
+    Ok(())
+}
+
+#[test]
+fn timeout_flag_ends_blocked_read() -> TestResult {
+    // the writer keeps the pipe open long after its first two lines
+    let mut writer = std::process::Command::new("sh")
+        .arg("-c")
+        .arg("printf 'a\\nb\\n'; exec sleep 10")
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    let started = Instant::now();
+    let output = std::process::Command::new(cargo_bin("rhead"))
+        .arg("--timeout")
+        .arg("300ms")
+        .stdin(Stdio::from(writer.stdout.take().unwrap()))
+        .output()?;
+    writer.kill()?;
+    writer.wait()?;
+
+    assert!(started.elapsed() < Duration::from_secs(5));
+    output.assert().code(3).stdout("a\nb\n");
+
+    Ok(())
+}
+
+#[test]
+fn max_bytes_flag_caps_input() -> TestResult {
+    let mut cmd = Command::cargo_bin("rhead")?;
+    cmd.arg("--max-bytes")
+        .arg("10")
+        .write_stdin(numbered_lines(5))
+        .assert()
+        .code(2)
+        .stdout("line 1\nlin");
+
+    let mut cmd = Command::cargo_bin("rhead")?;
+    cmd.arg("--max-bytes")
+        .arg("1K")
+        .arg("-c")
+        .arg("2000")
+        .write_stdin(numbered_lines(500))
+        .assert()
+        .code(2)
+        .stdout(numbered_lines(500)[..1024].to_string());
+
+    // input that ends right at the budget wasn't cut short, be it a file
+    // or a pipe whose writer is done
+    let file = assert_fs::NamedTempFile::new("data.txt")?;
+    file.write_str(&numbered_lines(1))?;
+    let mut cmd = Command::cargo_bin("rhead")?;
+    cmd.arg("--max-bytes")
+        .arg("7")
+        .pipe_stdin(file.path())?
+        .assert()
+        .code(0)
+        .stdout("line 1\n");
+
+    let mut writer = std::process::Command::new("printf")
+        .arg("line 1\\n")
+        .stdout(Stdio::piped())
+        .spawn()?;
+    writer.wait()?;
+    let output = std::process::Command::new(cargo_bin("rhead"))
+        .arg("--max-bytes")
+        .arg("7")
+        .stdin(Stdio::from(writer.stdout.take().unwrap()))
+        .output()?;
+    output.assert().code(0).stdout("line 1\n");
+
+    Ok(())
+}
+
+#[test]
+fn max_bytes_flag_stops_at_budget_of_stalled_pipe() -> TestResult {
+    // the writer keeps the pipe open long after the budget is used up
+    let mut writer = std::process::Command::new("sh")
+        .arg("-c")
+        .arg("printf 'aaaa\\n'; exec sleep 10")
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    let started = Instant::now();
+    let output = std::process::Command::new(cargo_bin("rhead"))
+        .arg("--max-bytes")
+        .arg("5")
+        .arg("-n")
+        .arg("100")
+        .stdin(Stdio::from(writer.stdout.take().unwrap()))
+        .output()?;
+    writer.kill()?;
+    writer.wait()?;
+
+    assert!(started.elapsed() < Duration::from_secs(5));
+    output.assert().code(2).stdout("aaaa\n");
+
+    Ok(())
+}
+
+#[test]
+fn max_bytes_flag_leaves_rest_of_pipe_unread() -> TestResult {
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c")
+        .arg(format!(
+            "{} --exact --max-bytes 3 -n 5; echo $?; cat",
+            cargo_bin("rhead").display()
+        ))
+        .write_stdin("ab\ncd\n")
+        .assert()
+        .code(0)
+        .stdout("ab\n2\ncd\n");
+
+    Ok(())
+}
+
+#[test]
+fn first_limit_hit_wins() -> TestResult {
+    let mut cmd = Command::cargo_bin("rhead")?;
+    cmd.arg("--max-bytes")
+        .arg("1MB")
+        .arg("--timeout")
+        .arg("10s")
+        .arg("-n")
+        .arg("2")
+        .write_stdin(numbered_lines(5))
+        .assert()
+        .code(0)
+        .stdout(numbered_lines(2));
+
+    Ok(())
+}
+
+#[test]
+fn limit_flags_reject_bad_values() -> TestResult {
+    for (flag, value) in [
+        ("--timeout", "soon"),
+        ("--timeout", "5x"),
+        ("--max-bytes", "1Q"),
+    ] {
+        let mut cmd = Command::cargo_bin("rhead")?;
+        cmd.arg(flag)
+            .arg(value)
+            .assert()
+            .code(1)
+            .stderr(predicate::str::contains(flag));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn j_flag_matches_sequential_output() -> TestResult {
+    let dir = assert_fs::TempDir::new()?;
+    let mut paths = Vec::new();
+    for n in 0..40 {
+        let file = dir.child(format!("{}.txt", n));
+        // a few files span several output chunks
+        file.write_str(&numbered_lines(if n % 10 == 0 { 20000 } else { n }))?;
+        paths.push(file.path().to_path_buf());
+    }
+    paths.insert(7, dir.path().join("missing.txt"));
+
+    for args in [vec![], vec!["-c", "100000"], vec!["--ranges", "2-3,-2"]] {
+        let sequential = Command::cargo_bin("rhead")?
+            .args(&args)
+            .args(&paths)
+            .output()?;
+        let mut cmd = Command::cargo_bin("rhead")?;
+        cmd.args(["-j", "4"])
+            .args(&args)
+            .args(&paths)
+            .assert()
+            .code(1)
+            .stdout(sequential.stdout)
+            .stderr(sequential.stderr);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn j_flag_conflicts_with_limits() -> TestResult {
+    let mut cmd = Command::cargo_bin("rhead")?;
+    cmd.arg("-j")
+        .arg("2")
+        .arg("--timeout")
+        .arg("1s")
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("USAGE"));
+
+    Ok(())
+}
+
+const CSV_EXPORT: &str = "id,name,notes\r\n\
+                          1,Ada,\"first line\r\nsecond line\"\r\n\
+                          2,Grace,\"said \"\"hi\"\"\"\r\n\
+                          3,Linus,\"a\nb\nc\"\r\n\
+                          4,Ken,plain\r\n";
+
+#[test]
+fn csv_flag_counts_records_not_lines() -> TestResult {
+    let mut cmd = Command::cargo_bin("rhead")?;
+    cmd.arg("--csv")
+        .arg("-n")
+        .arg("4")
+        .write_stdin(CSV_EXPORT)
+        .assert()
+        .code(0)
+        .stdout(
+            "id,name,notes\r\n\
+             1,Ada,\"first line\r\nsecond line\"\r\n\
+             2,Grace,\"said \"\"hi\"\"\"\r\n\
+             3,Linus,\"a\nb\nc\"\r\n",
+        );
+
+    Ok(())
+}
+
+#[test]
+fn keep_header_flag_prints_header_once() -> TestResult {
+    let dir = assert_fs::TempDir::new()?;
+    let first = dir.child("first.csv");
+    first.write_str(CSV_EXPORT)?;
+    let second = dir.child("second.csv");
+    second.write_str("id,name,notes\r\n5,Barbara,\"x\r\ny\"\r\n6,Dennis,z\r\n")?;
+    let empty = dir.child("empty.csv");
+    empty.touch()?;
+    let missing = dir.path().join("missing.csv");
+
+    let expected = "id,name,notes\r\n\
+                    1,Ada,\"first line\r\nsecond line\"\r\n\
+                    5,Barbara,\"x\r\ny\"\r\n";
+    for jobs in ["1", "2"] {
+        let mut cmd = Command::cargo_bin("rhead")?;
+        cmd.args(["--csv", "--keep-header", "-n", "1", "-j", jobs])
+            .arg(&missing)
+            .arg(empty.path())
+            .arg(first.path())
+            .arg(second.path())
+            .assert()
+            .code(1)
+            .stdout(expected);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn keep_header_flag_requires_csv() -> TestResult {
+    let mut cmd = Command::cargo_bin("rhead")?;
+    cmd.arg("--keep-header")
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("USAGE"));
+
+    Ok(())
+}
+
+#[test]
+fn dash_reads_stdin_in_file_list() -> TestResult {
+    let file = assert_fs::NamedTempFile::new("data.txt")?;
+    file.write_str(&numbered_lines(3))?;
+
+    let mut cmd = Command::cargo_bin("rhead")?;
+    cmd.arg("-n")
+        .arg("1")
+        .arg(file.path())
+        .arg("-")
+        .write_stdin("from stdin\nmore\n")
+        .assert()
+        .code(0)
+        .stdout(format!(
+            "==> {} <==\nline 1\n\n==> standard input <==\nfrom stdin\n",
+            file.path().display()
+        ));
+
+    Ok(())
+}
+
+#[test]
+fn q_flag_hides_headers() -> TestResult {
+    let file = assert_fs::NamedTempFile::new("data.txt")?;
+    file.write_str(&numbered_lines(3))?;
+
+    for flag in ["-q", "--quiet", "--silent"] {
+        let mut cmd = Command::cargo_bin("rhead")?;
+        cmd.args([flag, "-n", "1"])
+            .arg(file.path())
+            .arg(file.path())
+            .assert()
+            .code(0)
+            .stdout("line 1\nline 1\n");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn v_flag_shows_header_for_single_input() -> TestResult {
+    let mut cmd = Command::cargo_bin("rhead")?;
+    cmd.args(["-v", "-n", "1"])
+        .write_stdin(numbered_lines(3))
+        .assert()
+        .code(0)
+        .stdout("==> standard input <==\nline 1\n");
+
+    Ok(())
+}
+
+#[test]
+fn last_of_q_and_v_flags_wins() -> TestResult {
+    let mut cmd = Command::cargo_bin("rhead")?;
+    cmd.args(["-q", "-v", "-n", "1"])
+        .write_stdin(numbered_lines(3))
+        .assert()
+        .code(0)
+        .stdout("==> standard input <==\nline 1\n");
+
+    let mut cmd = Command::cargo_bin("rhead")?;
+    cmd.args(["-v", "-q", "-n", "1"])
+        .write_stdin(numbered_lines(3))
+        .assert()
+        .code(0)
+        .stdout("line 1\n");
+
+    Ok(())
+}
+
+#[test]
+fn fails_when_any_file_fails() -> TestResult {
+    let file = assert_fs::NamedTempFile::new("data.txt")?;
+    file.write_str(&numbered_lines(3))?;
+
+    for jobs in ["1", "2"] {
+        let mut cmd = Command::cargo_bin("rhead")?;
+        cmd.args(["-q", "-n", "1", "-j", jobs])
+            .arg("no/such/file.txt")
+            .arg(file.path())
+            .assert()
+            .code(1)
+            .stderr(predicate::str::contains("rhead: no/such/file.txt"))
+            .stdout("line 1\n");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn keeps_going_after_read_error() -> TestResult {
+    let dir = assert_fs::TempDir::new()?;
+    let first = dir.child("first.txt");
+    first.write_str("a\n")?;
+    let second = dir.child("second.txt");
+    second.write_str("b\n")?;
+    let unreadable = dir.child("dir");
+    unreadable.create_dir_all()?;
+
+    let expected = format!(
+        "==> {} <==\na\n\n==> {} <==\n\n==> {} <==\nb\n",
+        first.path().display(),
+        unreadable.path().display(),
+        second.path().display()
+    );
+    for jobs in ["1", "2"] {
+        let mut cmd = Command::cargo_bin("rhead")?;
+        cmd.args(["-j", jobs])
+            .arg(first.path())
+            .arg(unreadable.path())
+            .arg(second.path())
+            .assert()
+            .code(1)
+            .stderr(format!(
+                "rhead: {}: Is a directory (os error 21)\n",
+                unreadable.path().display()
+            ))
+            .stdout(expected.clone());
+    }
+
+    Ok(())
+}
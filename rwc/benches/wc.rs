@@ -0,0 +1,67 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+// big enough for rwc to split the file across threads
+const FILE_SIZE: u64 = 64 * 1024 * 1024;
+
+const LINES: &[&str] = &[
+    "The quick brown fox jumps over the lazy dog.",
+    "  Indented\twith tabs\tand  double  spaces  ",
+    "Ünïcödé wörds, ünd ein paar Umlaute dazu.",
+    "日本語のテキストも少し混ぜておきます。",
+    "",
+    "short",
+];
+
+fn sample_file() -> PathBuf {
+    let path = Path::new(env!("CARGO_TARGET_TMPDIR")).join("rwc-bench.txt");
+    if path
+        .metadata()
+        .is_ok_and(|metadata| metadata.len() >= FILE_SIZE)
+    {
+        return path;
+    }
+
+    let mut file = BufWriter::new(File::create(&path).unwrap());
+    let mut written = 0;
+    for line in LINES.iter().cycle() {
+        writeln!(file, "{}", line).unwrap();
+        written += line.len() as u64 + 1;
+        if written >= FILE_SIZE {
+            break;
+        }
+    }
+    file.flush().unwrap();
+    path
+}
+
+fn run(program: &str, flags: &[&str], path: &Path) {
+    let status = Command::new(program)
+        .args(flags)
+        .arg(path)
+        .env("LC_ALL", "C.UTF-8")
+        .stdout(Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
+
+fn compare_with_gnu_wc(c: &mut Criterion) {
+    let path = sample_file();
+    for flags in [&["-l"][..], &[], &["-m"], &["-L"]] {
+        let mut group = c.benchmark_group(format!("wc {}", flags.join(" ")).trim_end().to_string());
+        group.throughput(Throughput::Bytes(FILE_SIZE));
+        group.sample_size(10);
+        group.bench_function("rwc", |b| {
+            b.iter(|| run(env!("CARGO_BIN_EXE_rwc"), flags, &path))
+        });
+        group.bench_function("gnu", |b| b.iter(|| run("wc", flags, &path)));
+        group.finish();
+    }
+}
+
+criterion_group!(benches, compare_with_gnu_wc);
+criterion_main!(benches);
@@ -0,0 +1,352 @@
+use std::fmt;
+use std::path::Path;
+
+/// A language whose lines `--code` can tell apart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Language {
+    C,
+    Python,
+    Rust,
+    Shell,
+    Toml,
+}
+
+impl Language {
+    /// Guesses the language from a file's extension.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "c" | "h" | "cc" | "cpp" | "cxx" | "hh" | "hpp" | "hxx" => Some(Language::C),
+            "py" | "pyw" | "pyi" => Some(Language::Python),
+            "rs" => Some(Language::Rust),
+            "sh" | "bash" | "zsh" | "ksh" | "dash" => Some(Language::Shell),
+            "toml" => Some(Language::Toml),
+            _ => None,
+        }
+    }
+
+    /// Guesses the language from a `#!` line, e.g. `#!/usr/bin/env python3`.
+    pub fn from_shebang(first_line: &[u8]) -> Option<Self> {
+        let line = first_line.strip_prefix(b"#!")?;
+        let line = String::from_utf8_lossy(line.split(|&byte| byte == b'\n').next()?);
+        let mut words = line.split_whitespace();
+        let mut program = words.next()?.rsplit('/').next()?;
+        if program == "env" {
+            program = words.find(|word| !word.starts_with('-'))?;
+        }
+
+        match program {
+            "sh" | "ash" | "bash" | "dash" | "ksh" | "zsh" => Some(Language::Shell),
+            _ if program.starts_with("python") => Some(Language::Python),
+            _ => None,
+        }
+    }
+
+    fn syntax(self) -> &'static Syntax {
+        match self {
+            Language::C => &C,
+            Language::Python => &PYTHON,
+            Language::Rust => &RUST,
+            Language::Shell => &SHELL,
+            Language::Toml => &TOML,
+        }
+    }
+}
+
+impl fmt::Display for Language {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Language::C => "C",
+            Language::Python => "Python",
+            Language::Rust => "Rust",
+            Language::Shell => "Shell",
+            Language::Toml => "TOML",
+        };
+        f.write_str(name)
+    }
+}
+
+/// A kind of string literal.
+struct Quote {
+    open: &'static str,
+    close: &'static str,
+    // a backslash escapes the next character
+    escapes: bool,
+    // the string may go on past the end of the line
+    multiline: bool,
+}
+
+const fn quote(open: &'static str, escapes: bool, multiline: bool) -> Quote {
+    Quote {
+        open,
+        close: open,
+        escapes,
+        multiline,
+    }
+}
+
+/// Where comments and strings start and end in a language.
+struct Syntax {
+    line_comment: &'static str,
+    block_comment: Option<(&'static str, &'static str)>,
+    nested_block_comments: bool,
+    // longest openers first, so """ isn't taken for an empty string
+    quotes: &'static [Quote],
+    // comments only start at the start of a word, as `#` in shell
+    comment_starts_word: bool,
+    // r"...", r#"..."#, and 'c' char literals apart from 'a lifetimes
+    rust_literals: bool,
+}
+
+const C: Syntax = Syntax {
+    line_comment: "//",
+    block_comment: Some(("/*", "*/")),
+    nested_block_comments: false,
+    quotes: &[quote("\"", true, false), quote("'", true, false)],
+    comment_starts_word: false,
+    rust_literals: false,
+};
+
+const PYTHON: Syntax = Syntax {
+    line_comment: "#",
+    block_comment: None,
+    nested_block_comments: false,
+    quotes: &[
+        quote("\"\"\"", true, true),
+        quote("'''", true, true),
+        quote("\"", true, false),
+        quote("'", true, false),
+    ],
+    comment_starts_word: false,
+    rust_literals: false,
+};
+
+const RUST: Syntax = Syntax {
+    line_comment: "//",
+    block_comment: Some(("/*", "*/")),
+    nested_block_comments: true,
+    quotes: &[quote("\"", true, true)],
+    comment_starts_word: false,
+    rust_literals: true,
+};
+
+const SHELL: Syntax = Syntax {
+    line_comment: "#",
+    block_comment: None,
+    nested_block_comments: false,
+    quotes: &[quote("\"", true, true), quote("'", false, true)],
+    comment_starts_word: true,
+    rust_literals: false,
+};
+
+const TOML: Syntax = Syntax {
+    line_comment: "#",
+    block_comment: None,
+    nested_block_comments: false,
+    quotes: &[
+        quote("\"\"\"", true, true),
+        quote("'''", false, true),
+        quote("\"", true, false),
+        quote("'", false, false),
+    ],
+    comment_starts_word: false,
+    rust_literals: false,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LineKind {
+    Code,
+    Comment,
+    Blank,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Code,
+    // inside this many levels of block comments
+    BlockComment(usize),
+    // inside a string, by its index in the language's quotes
+    Str(usize),
+    // inside a Rust raw string closed by a quote and this many #s
+    RawStr(usize),
+}
+
+/// Classifies lines one after another, carrying comments and strings over
+/// from one line to the next.
+pub struct Classifier {
+    syntax: Option<&'static Syntax>,
+    state: State,
+}
+
+impl Classifier {
+    /// Without a language, every line with anything on it is code.
+    pub fn new(language: Option<Language>) -> Self {
+        Classifier {
+            syntax: language.map(Language::syntax),
+            state: State::Code,
+        }
+    }
+
+    /// Classifies a line, without its line break.
+    ///
+    /// A line with any code on it is code, even if it has a comment too.
+    /// Blank lines are blank even inside a block comment, but not inside a
+    /// string, where they are part of the code.
+    pub fn classify(&mut self, line: &str) -> LineKind {
+        let in_string = matches!(self.state, State::Str(_) | State::RawStr(_));
+        let Some(syntax) = self.syntax else {
+            return if line.trim().is_empty() {
+                LineKind::Blank
+            } else {
+                LineKind::Code
+            };
+        };
+        if line.trim().is_empty() && !in_string {
+            return LineKind::Blank;
+        }
+
+        let (mut code, mut comment) = (in_string, false);
+        let mut rest = line;
+        let mut previous = None;
+        while let Some(char) = rest.chars().next() {
+            let (advance, is_code, is_comment) = self.step(syntax, rest, previous);
+            code |= is_code;
+            comment |= is_comment;
+            if advance == 0 {
+                // a line comment runs to the end of the line
+                break;
+            }
+            previous = rest[..advance].chars().next_back().or(Some(char));
+            rest = &rest[advance..];
+        }
+
+        if let State::Str(index) = self.state {
+            let quote = &syntax.quotes[index];
+            // a backslash at the end of a line carries a string over in C
+            let continued = quote.escapes && line.ends_with('\\');
+            if !quote.multiline && !continued {
+                self.state = State::Code;
+            }
+        }
+
+        if code {
+            LineKind::Code
+        } else if comment {
+            LineKind::Comment
+        } else {
+            LineKind::Blank
+        }
+    }
+
+    // Takes one token off the start of `rest`, returning how long it was
+    // (0 for a line comment) and whether it was code and/or comment.
+    fn step(&mut self, syntax: &Syntax, rest: &str, previous: Option<char>) -> (usize, bool, bool) {
+        let char_len = rest.chars().next().map_or(1, char::len_utf8);
+        match self.state {
+            State::BlockComment(depth) => {
+                let (open, close) = syntax.block_comment.unwrap();
+                if syntax.nested_block_comments && rest.starts_with(open) {
+                    self.state = State::BlockComment(depth + 1);
+                    (open.len(), false, true)
+                } else if rest.starts_with(close) {
+                    self.state = match depth {
+                        1 => State::Code,
+                        _ => State::BlockComment(depth - 1),
+                    };
+                    (close.len(), false, true)
+                } else {
+                    let blank = rest.starts_with(char::is_whitespace);
+                    (char_len, false, !blank)
+                }
+            }
+            State::Str(index) => {
+                let quote = &syntax.quotes[index];
+                if quote.escapes && rest.starts_with('\\') {
+                    let escaped = rest[1..].chars().next().map_or(0, char::len_utf8);
+                    (1 + escaped, true, false)
+                } else if rest.starts_with(quote.close) {
+                    self.state = State::Code;
+                    (quote.close.len(), true, false)
+                } else {
+                    (char_len, true, false)
+                }
+            }
+            State::RawStr(hashes) => {
+                let closes = rest.starts_with('"')
+                    && rest[1..].bytes().take_while(|&byte| byte == b'#').count() >= hashes;
+                if closes {
+                    self.state = State::Code;
+                    (1 + hashes, true, false)
+                } else {
+                    (char_len, true, false)
+                }
+            }
+            State::Code => self.step_code(syntax, rest, previous, char_len),
+        }
+    }
+
+    fn step_code(
+        &mut self,
+        syntax: &Syntax,
+        rest: &str,
+        previous: Option<char>,
+        char_len: usize,
+    ) -> (usize, bool, bool) {
+        if rest.starts_with(char::is_whitespace) {
+            return (char_len, false, false);
+        }
+
+        let word_start = previous.is_none_or(|previous| {
+            previous.is_whitespace() || matches!(previous, ';' | '&' | '|' | '(' | ')')
+        });
+        if rest.starts_with(syntax.line_comment) && (!syntax.comment_starts_word || word_start) {
+            return (0, false, true);
+        }
+        if let Some((open, _)) = syntax.block_comment {
+            if rest.starts_with(open) {
+                self.state = State::BlockComment(1);
+                return (open.len(), false, true);
+            }
+        }
+
+        if syntax.rust_literals {
+            if let Some(len) = self.rust_literal(rest, previous) {
+                return (len, true, false);
+            }
+        }
+        if let Some(index) = syntax
+            .quotes
+            .iter()
+            .position(|quote| rest.starts_with(quote.open))
+        {
+            self.state = State::Str(index);
+            return (syntax.quotes[index].open.len(), true, false);
+        }
+        (char_len, true, false)
+    }
+
+    // Steps over the start of a raw string or the whole of a char literal,
+    // which unlike a lifetime is closed by another quote.
+    fn rust_literal(&mut self, rest: &str, previous: Option<char>) -> Option<usize> {
+        let identifier =
+            previous.is_some_and(|previous| previous.is_alphanumeric() || previous == '_');
+        let raw = rest.strip_prefix("br").or_else(|| rest.strip_prefix('r'));
+        if let (Some(raw), false) = (raw, identifier) {
+            let hashes = raw.bytes().take_while(|&byte| byte == b'#').count();
+            if raw[hashes..].starts_with('"') {
+                self.state = State::RawStr(hashes);
+                return Some(rest.len() - raw.len() + hashes + 1);
+            }
+        }
+
+        let literal = rest.strip_prefix('\'')?;
+        if let Some(escape) = literal.strip_prefix('\\') {
+            let end = escape.find('\'')?;
+            return Some(2 + end + 1);
+        }
+        let char = literal.chars().next()?;
+        literal[char.len_utf8()..]
+            .starts_with('\'')
+            .then(|| 2 + char.len_utf8())
+    }
+}
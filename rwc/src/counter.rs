@@ -0,0 +1,673 @@
+use crate::code::{Classifier, Language, LineKind};
+use crate::matches::Matcher;
+use crate::stats::{LengthUnit, LineStats};
+use crate::top::{TopWords, WordSplitter};
+use crate::{Count, WordMode};
+use std::mem;
+use std::sync::Arc;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthChar;
+
+/// Which of the costlier counts are wanted; lines and bytes always are.
+#[derive(Debug, Clone, Copy)]
+pub struct Needs {
+    pub words: bool,
+    pub chars: bool,
+    pub graphemes: bool,
+    pub max_line_length: bool,
+    pub code: bool,
+    pub line_lengths: Option<LengthUnit>,
+}
+
+/// How much of a line is held on to, at most, for the segments it is cut
+/// into to be counted, before those that can't change any more are.
+const MAX_PENDING: usize = 64 * 1024;
+
+/// How a character affects word counting in GNU wc.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CharClass {
+    // ends a word
+    Space,
+    // starts a word, or continues one
+    Printable,
+    // neither starts nor ends a word, like control characters
+    Other,
+}
+
+impl CharClass {
+    fn of(char: char) -> Self {
+        match char {
+            // whitespace that isn't printable doesn't split words in GNU wc
+            '\u{85}' | '\u{2028}' | '\u{2029}' => CharClass::Other,
+            _ if char.is_whitespace() => CharClass::Space,
+            _ if char.is_control() => CharClass::Other,
+            _ => CharClass::Printable,
+        }
+    }
+}
+
+// CharClass::of for every ASCII byte, so ASCII text skips decoding
+const ASCII_CLASSES: [CharClass; 128] = {
+    let mut classes = [CharClass::Printable; 128];
+    let mut byte = 0;
+    while byte < 128 {
+        classes[byte] = match byte as u8 {
+            b' ' | b'\t' | b'\n' | b'\r' | 0x0b | 0x0c => CharClass::Space,
+            0..=0x1f | 0x7f => CharClass::Other,
+            _ => CharClass::Printable,
+        };
+        byte += 1;
+    }
+    classes
+};
+
+/// How a counted stretch of input starts and ends, which is what it takes
+/// to stitch the counts of neighbouring stretches back together.
+#[derive(Debug, Clone, Copy)]
+pub struct Edges {
+    // the first character that starts or ends words starts one
+    pub starts_in_word: bool,
+    // the stretch ends inside a word, or None if it never starts or ends one
+    pub ends_in_word: Option<bool>,
+}
+
+/// What a [`Counter`] counted, as GNU wc counts it.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Counts {
+    /// Newlines.
+    pub lines: u64,
+    /// Runs of printable characters between whitespace.
+    pub words: u64,
+    /// UTF-8 characters, leaving out bytes that aren't part of one.
+    pub chars: u64,
+    pub bytes: u64,
+    /// The display width of the widest line, as `wc -L` measures it.
+    pub max_line_length: u64,
+}
+
+/// Counts a stream handed over in chunks of any size.
+///
+/// Lines and bytes are counted with vectorised scans; everything else
+/// decodes UTF-8, carrying word and line state across chunk edges. An
+/// undecodable byte is neither a character nor part of a word, as in GNU wc.
+///
+/// Chunks can be cut anywhere, even inside a word or a character:
+///
+/// ```
+/// let mut counter = rwc::Counter::new();
+/// counter.update(b"one tw");
+/// counter.update(b"o \xe4");
+/// counter.update(b"\xbd\xa0\n");
+/// let counts = counter.finish();
+/// assert_eq!((counts.lines, counts.words, counts.chars), (1, 3, 10));
+/// ```
+pub struct Counter {
+    count: Count,
+    word_mode: WordMode,
+    needs: Needs,
+    // a UTF-8 sequence cut off by the end of the last chunk
+    pending: Vec<u8>,
+    // the start of a line the next chunk goes on with, for what counts
+    // whole lines: code and matches
+    line: Vec<u8>,
+    code: Option<Classifier>,
+    matcher: Option<Arc<Matcher>>,
+    // room for the matcher to keep track of where matches end
+    match_ends: Vec<usize>,
+    top_words: Option<WordSplitter>,
+    line_lengths: Option<LineLengths>,
+    graphemes: Option<Segments>,
+    unicode_words: Option<Segments>,
+    in_word: bool,
+    // display width of the current line so far
+    column: u64,
+    first_class: Option<CharClass>,
+}
+
+impl Counter {
+    /// A counter of everything in [`Counts`].
+    pub fn new() -> Self {
+        let needs = Needs {
+            words: true,
+            chars: true,
+            graphemes: false,
+            max_line_length: true,
+            code: false,
+            line_lengths: None,
+        };
+        Counter::with_needs(WordMode::Whitespace, needs)
+    }
+
+    pub(crate) fn with_needs(word_mode: WordMode, needs: Needs) -> Self {
+        let mut count = Count::new();
+        if needs.line_lengths.is_some() {
+            count.line_stats = Some(LineStats::default());
+        }
+        Counter {
+            count,
+            word_mode,
+            needs,
+            pending: Vec::new(),
+            line: Vec::new(),
+            code: None,
+            matcher: None,
+            match_ends: Vec::new(),
+            top_words: None,
+            line_lengths: needs.line_lengths.map(LineLengths::new),
+            graphemes: needs
+                .graphemes
+                .then(|| Segments::new(Segmentation::Graphemes)),
+            unicode_words: (needs.words && word_mode == WordMode::Unicode)
+                .then(|| Segments::new(Segmentation::Words)),
+            in_word: false,
+            column: 0,
+            first_class: None,
+        }
+    }
+
+    /// Also sorts lines into code, comments and blanks, as in `language`.
+    pub(crate) fn count_code(&mut self, language: Option<Language>) {
+        self.count.language = language;
+        self.code = Some(Classifier::new(language));
+    }
+
+    /// Also counts matches of the patterns of `matcher`.
+    pub(crate) fn count_matches(&mut self, matcher: Arc<Matcher>) {
+        self.count.matches = vec![0; matcher.names().len()];
+        self.matcher = Some(matcher);
+    }
+
+    /// Also counts each word towards the most frequent words of `top_words`.
+    pub(crate) fn count_top_words(&mut self, top_words: Arc<TopWords>) {
+        self.top_words = Some(WordSplitter::new(top_words));
+    }
+
+    /// Counts the next chunk of the stream.
+    pub fn update(&mut self, mut bytes: &[u8]) {
+        self.count.bytes += bytes.len() as u64;
+        self.count.lines += memchr::memchr_iter(b'\n', bytes).count() as u64;
+        if self.counts_whole_lines() {
+            self.split_lines(bytes);
+        }
+        if let Some(line_lengths) = &mut self.line_lengths {
+            line_lengths.update(bytes, &mut self.count);
+        }
+        if let Some(top_words) = &mut self.top_words {
+            top_words.update(bytes);
+        }
+        if let Some(graphemes) = &mut self.graphemes {
+            self.count.graphemes += graphemes.update(bytes);
+        }
+        if let Some(unicode_words) = &mut self.unicode_words {
+            self.count.words += unicode_words.update(bytes);
+        }
+        if !self.decodes_chars() {
+            return;
+        }
+
+        // finish a character cut off by the end of the last chunk
+        while !self.pending.is_empty() {
+            match bytes.first() {
+                Some(&byte) if is_continuation(byte) => {
+                    self.pending.push(byte);
+                    bytes = &bytes[1..];
+                    if self.pending.len() == sequence_len(self.pending[0]) {
+                        let pending = mem::take(&mut self.pending);
+                        self.decode(&pending);
+                    }
+                }
+                Some(_) => self.pending.clear(),
+                None => return,
+            }
+        }
+
+        let end = bytes.len() - cut_off_len(bytes);
+        self.decode(&bytes[..end]);
+        self.pending.extend_from_slice(&bytes[end..]);
+    }
+
+    /// What has been counted so far, leaving out anything still pending.
+    pub(crate) fn snapshot(&self) -> Count {
+        let mut count = self.count.clone();
+        count.max_line_length = count.max_line_length.max(self.column);
+        count
+    }
+
+    /// Counts whatever is left over at the end of the stream.
+    pub fn finish(self) -> Counts {
+        let count = self.into_count();
+        Counts {
+            lines: count.lines,
+            words: count.words,
+            chars: count.chars,
+            bytes: count.bytes,
+            max_line_length: count.max_line_length,
+        }
+    }
+
+    pub(crate) fn into_count(self) -> Count {
+        self.finish_with_edges().0
+    }
+
+    pub(crate) fn finish_with_edges(mut self) -> (Count, Edges) {
+        // the last line, if it goes without a line break
+        if !self.line.is_empty() {
+            let line = mem::take(&mut self.line);
+            self.add_line(&line);
+        }
+        if let Some(line_lengths) = &mut self.line_lengths {
+            line_lengths.finish(&mut self.count);
+        }
+        if let Some(top_words) = &mut self.top_words {
+            top_words.finish();
+        }
+        if let Some(graphemes) = &mut self.graphemes {
+            self.count.graphemes += graphemes.finish();
+        }
+        if let Some(unicode_words) = &mut self.unicode_words {
+            self.count.words += unicode_words.finish();
+        }
+        self.count.max_line_length = self.count.max_line_length.max(self.column);
+
+        let edges = Edges {
+            starts_in_word: self.first_class == Some(CharClass::Printable),
+            ends_in_word: self.first_class.map(|_| self.in_word),
+        };
+        (self.count, edges)
+    }
+
+    fn counts_whole_lines(&self) -> bool {
+        self.code.is_some() || self.matcher.is_some()
+    }
+
+    // Hands each line the chunk finishes to add_line, without its line
+    // break, and keeps the start of an unfinished one for the next chunk.
+    fn split_lines(&mut self, bytes: &[u8]) {
+        let mut start = 0;
+        for end in memchr::memchr_iter(b'\n', bytes) {
+            if self.line.is_empty() {
+                self.add_line(&bytes[start..end]);
+            } else {
+                let mut line = mem::take(&mut self.line);
+                line.extend_from_slice(&bytes[start..end]);
+                self.add_line(&line);
+                line.clear();
+                self.line = line;
+            }
+            start = end + 1;
+        }
+        self.line.extend_from_slice(&bytes[start..]);
+    }
+
+    fn add_line(&mut self, line: &[u8]) {
+        if let Some(classifier) = &mut self.code {
+            let text = String::from_utf8_lossy(line);
+            match classifier.classify(text.strip_suffix('\r').unwrap_or(&text)) {
+                LineKind::Code => self.count.code += 1,
+                LineKind::Comment => self.count.comments += 1,
+                LineKind::Blank => self.count.blanks += 1,
+            }
+        }
+        // matches can't span lines
+        if let Some(matcher) = &self.matcher {
+            matcher.count(line, &mut self.count.matches, &mut self.match_ends);
+        }
+    }
+
+    // whether anything is counted character by character
+    fn decodes_chars(&self) -> bool {
+        (self.needs.words && self.word_mode == WordMode::Whitespace)
+            || self.needs.chars
+            || self.needs.max_line_length
+    }
+
+    fn decode(&mut self, bytes: &[u8]) {
+        for chunk in bytes.utf8_chunks() {
+            let text = chunk.valid();
+            if self.needs.chars {
+                self.count.chars += count_chars(text.as_bytes());
+            }
+
+            let whitespace_words = self.needs.words && self.word_mode == WordMode::Whitespace;
+            if !whitespace_words && !self.needs.max_line_length {
+                continue;
+            }
+
+            if text.is_ascii() {
+                for &byte in text.as_bytes() {
+                    self.add_char(byte as char, ASCII_CLASSES[byte as usize], whitespace_words);
+                }
+            } else {
+                for char in text.chars() {
+                    self.add_char(char, CharClass::of(char), whitespace_words);
+                }
+            }
+        }
+    }
+
+    fn add_char(&mut self, char: char, class: CharClass, whitespace_words: bool) {
+        if whitespace_words {
+            // a word is counted as it starts
+            match class {
+                CharClass::Space => self.in_word = false,
+                CharClass::Printable if !self.in_word => {
+                    self.in_word = true;
+                    self.count.words += 1;
+                }
+                _ => {}
+            }
+            if class != CharClass::Other && self.first_class.is_none() {
+                self.first_class = Some(class);
+            }
+        }
+
+        if self.needs.max_line_length {
+            match char {
+                '\n' | '\r' | '\x0c' => {
+                    self.count.max_line_length = self.count.max_line_length.max(self.column);
+                    self.column = 0;
+                }
+                _ => self.column = advance_column(self.column, char),
+            }
+        }
+    }
+}
+
+impl Default for Counter {
+    fn default() -> Self {
+        Counter::new()
+    }
+}
+
+/// What [`Segments`] cuts text into, at UAX #29 boundaries.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Segmentation {
+    Graphemes,
+    // segments with a letter or digit in them
+    Words,
+}
+
+/// Counts grapheme clusters or words, holding on to no more of a line than
+/// the end of it that the next chunk may still change the segments of.
+struct Segments {
+    kind: Segmentation,
+    pending: Vec<u8>,
+    // pending goes on with a segment that was counted already, cut in two
+    // for being too long to hold on to
+    continued: bool,
+}
+
+impl Segments {
+    fn new(kind: Segmentation) -> Self {
+        Segments {
+            kind,
+            pending: Vec::new(),
+            continued: false,
+        }
+    }
+
+    fn update(&mut self, bytes: &[u8]) -> u64 {
+        let mut count = 0;
+        // a newline always ends a cluster, and a word too
+        match memchr::memrchr(b'\n', bytes) {
+            Some(end) => {
+                let (lines, rest) = bytes.split_at(end + 1);
+                let mut pending = mem::take(&mut self.pending);
+                pending.extend_from_slice(lines);
+                count += self.count(&pending);
+                pending.clear();
+                pending.extend_from_slice(rest);
+                self.pending = pending;
+            }
+            None => self.pending.extend_from_slice(bytes),
+        }
+        if self.pending.len() > MAX_PENDING {
+            count += self.count_settled();
+        }
+        count
+    }
+
+    fn finish(&mut self) -> u64 {
+        let pending = mem::take(&mut self.pending);
+        self.count(&pending)
+    }
+
+    fn count(&mut self, bytes: &[u8]) -> u64 {
+        let mut count = 0;
+        for chunk in bytes.utf8_chunks() {
+            count += match self.kind {
+                Segmentation::Graphemes => count_graphemes(chunk.valid()),
+                Segmentation::Words => chunk.valid().unicode_words().count() as u64,
+            };
+        }
+        let first = bytes.utf8_chunks().next().map_or("", |chunk| chunk.valid());
+        if mem::take(&mut self.continued) && self.counted_at_start(first) {
+            count -= 1;
+        }
+        count
+    }
+
+    // Counts every segment of a long line that what comes next can't
+    // change. A single segment that long is cut in two, and its second half
+    // held on to after its first character, so that it still goes on with
+    // the same segment but isn't counted again.
+    fn count_settled(&mut self) -> u64 {
+        let pending = mem::take(&mut self.pending);
+        let end = pending.len() - cut_off_len(&pending);
+        let (start, text) = last_valid_run(&pending[..end]);
+        let settled = start + self.unsettled_start(text);
+        if settled > 0 {
+            let count = self.count(&pending[..settled]);
+            self.pending = pending[settled..].to_vec();
+            return count;
+        }
+
+        let half = text.floor_char_boundary(text.len() / 2);
+        let first = text.chars().next().map_or(0, char::len_utf8);
+        let count = self.count(&pending[..half]);
+        self.continued = self.counted_at_end(&text[..half]);
+        self.pending = [&pending[..first], &pending[half..]].concat();
+        count
+    }
+
+    // where the segments that more text may change start
+    fn unsettled_start(&self, text: &str) -> usize {
+        match self.kind {
+            Segmentation::Graphemes => text
+                .grapheme_indices(true)
+                .next_back()
+                .map_or(0, |(i, _)| i),
+            Segmentation::Words => {
+                // the boundary before the last segment can still go away,
+                // as "can" and "'" join when "t" follows
+                let mut starts = text.split_word_bound_indices().rev().map(|(i, _)| i);
+                let last = starts.next().unwrap_or(0);
+                starts.next().filter(|&start| start > 0).unwrap_or(last)
+            }
+        }
+    }
+
+    fn counted_at_start(&self, text: &str) -> bool {
+        match self.kind {
+            Segmentation::Graphemes => !text.is_empty(),
+            Segmentation::Words => text
+                .unicode_word_indices()
+                .next()
+                .is_some_and(|(i, _)| i == 0),
+        }
+    }
+
+    fn counted_at_end(&self, text: &str) -> bool {
+        match self.kind {
+            Segmentation::Graphemes => !text.is_empty(),
+            Segmentation::Words => text
+                .unicode_word_indices()
+                .next_back()
+                .is_some_and(|(i, word)| i + word.len() == text.len()),
+        }
+    }
+}
+
+// The text after the last byte that isn't UTF-8, if any, and where it
+// starts; what comes before such a byte can't join what comes after.
+fn last_valid_run(bytes: &[u8]) -> (usize, &str) {
+    let (mut offset, mut run) = (0, (0, ""));
+    for chunk in bytes.utf8_chunks() {
+        let start = offset;
+        offset += chunk.valid().len() + chunk.invalid().len();
+        run = if chunk.invalid().is_empty() {
+            (start, chunk.valid())
+        } else {
+            (offset, "")
+        };
+    }
+    run
+}
+
+/// Measures each line for --stats and --histogram.
+struct LineLengths {
+    unit: LengthUnit,
+    // the number of the line being measured
+    line: u64,
+    // in bytes, how long the line so far is and its last byte
+    len: u64,
+    last: Option<u8>,
+    // in columns, the line so far
+    partial: Vec<u8>,
+}
+
+impl LineLengths {
+    fn new(unit: LengthUnit) -> Self {
+        LineLengths {
+            unit,
+            line: 1,
+            len: 0,
+            last: None,
+            partial: Vec::new(),
+        }
+    }
+
+    fn update(&mut self, bytes: &[u8], count: &mut Count) {
+        let mut start = 0;
+        for end in memchr::memchr_iter(b'\n', bytes) {
+            self.extend(&bytes[start..end]);
+            self.add_line(count);
+            start = end + 1;
+        }
+        self.extend(&bytes[start..]);
+    }
+
+    fn finish(&mut self, count: &mut Count) {
+        if self.len > 0 {
+            self.add_line(count);
+        }
+    }
+
+    fn extend(&mut self, bytes: &[u8]) {
+        self.len += bytes.len() as u64;
+        self.last = bytes.last().copied().or(self.last);
+        if self.unit == LengthUnit::Columns {
+            self.partial.extend_from_slice(bytes);
+        }
+    }
+
+    // lines are measured without their line break, \n or \r\n
+    fn add_line(&mut self, count: &mut Count) {
+        let cr = (self.last == Some(b'\r')) as u64;
+        let length = match self.unit {
+            LengthUnit::Bytes => self.len - cr,
+            LengthUnit::Columns => line_width(&self.partial),
+        };
+        let stats = count.line_stats.as_mut().unwrap();
+        stats.add(self.line, length, self.len == cr);
+        self.line += 1;
+        self.len = 0;
+        self.last = None;
+        self.partial.clear();
+    }
+}
+
+/// Adds up the counts of consecutive stretches of one input, each counted
+/// from a fresh Counter, undoing double counts of words split between them.
+pub fn stitch(stretches: impl IntoIterator<Item = (Count, Edges)>) -> Count {
+    let mut total = Count::new();
+    let mut in_word = false;
+    for (count, edges) in stretches {
+        total += count;
+        if in_word && edges.starts_in_word {
+            total.words -= 1;
+        }
+        in_word = edges.ends_in_word.unwrap_or(in_word);
+    }
+    total
+}
+
+// Moves along a line as GNU wc -L does: tabs stop every 8 columns, wide
+// characters take two, and other control characters none. \r and \f start
+// the line over, which is up to the caller.
+fn advance_column(column: u64, char: char) -> u64 {
+    match char {
+        '\t' => column + 8 - column % 8,
+        _ if char.is_control() => column,
+        _ => column + char.width().unwrap_or(0) as u64,
+    }
+}
+
+// The display width of a whole line, as -L measures it.
+fn line_width(line: &[u8]) -> u64 {
+    let (mut widest, mut column) = (0, 0);
+    for chunk in line.utf8_chunks() {
+        for char in chunk.valid().chars() {
+            match char {
+                '\r' | '\x0c' => {
+                    widest = widest.max(column);
+                    column = 0;
+                }
+                _ => column = advance_column(column, char),
+            }
+        }
+    }
+    widest.max(column)
+}
+
+fn count_chars(text: &[u8]) -> u64 {
+    // every character has exactly one byte that isn't a continuation byte;
+    // a plain loop like this one compiles to vector instructions
+    text.iter().filter(|&&byte| !is_continuation(byte)).count() as u64
+}
+
+// Counts extended grapheme clusters, of which \r\n is the only one in ASCII
+// that is more than one character.
+fn count_graphemes(text: &str) -> u64 {
+    if text.is_ascii() {
+        let crlfs = memchr::memmem::find_iter(text.as_bytes(), b"\r\n").count();
+        (text.len() - crlfs) as u64
+    } else {
+        text.graphemes(true).count() as u64
+    }
+}
+
+fn is_continuation(byte: u8) -> bool {
+    byte & 0b1100_0000 == 0b1000_0000
+}
+
+// how long the sequence a leading byte starts is, or 1 if it is invalid
+fn sequence_len(byte: u8) -> usize {
+    match byte {
+        0xc2..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf4 => 4,
+        _ => 1,
+    }
+}
+
+/// How many bytes at the end are a UTF-8 sequence the next chunk may complete.
+pub fn cut_off_len(bytes: &[u8]) -> usize {
+    for back in 1..=bytes.len().min(3) {
+        let byte = bytes[bytes.len() - back];
+        if !is_continuation(byte) {
+            return if sequence_len(byte) > back { back } else { 0 };
+        }
+    }
+    0
+}
@@ -0,0 +1,636 @@
+use crate::code::Language;
+use crate::stats::LineStats;
+use crate::{Config, Count, TotalMode};
+use std::fs::{self, File, Metadata};
+use std::io::{self, Write};
+use std::os::fd::AsFd;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::str::FromStr;
+
+/// How counts are printed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    // GNU wc's right aligned columns
+    Text,
+    // one object with a "files" array and the "total"
+    Json,
+    // in both, a byte that isn't UTF-8 is written as \xNN, in hex, and a
+    // backslash as \\
+    Csv,
+    Tsv,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            "csv" => Ok(Format::Csv),
+            "tsv" => Ok(Format::Tsv),
+            _ => Err(format!("unknown format '{}'", s)),
+        }
+    }
+}
+
+// the widest a histogram bar gets
+const BAR_WIDTH: u64 = 40;
+
+/// Prints a record per input as it is counted, then the total.
+pub struct Report<'a> {
+    config: &'a Config,
+    out: Box<dyn Write>,
+    records: usize,
+    // of each text column, as GNU wc works it out before counting
+    width: usize,
+}
+
+impl<'a> Report<'a> {
+    /// Starts a report on the given inputs, or on inputs still to be named
+    /// when `paths` is None, as with a file list too big to read ahead.
+    pub fn start<'p>(
+        config: &'a Config,
+        mut out: Box<dyn Write>,
+        paths: Option<impl IntoIterator<Item = &'p Path>>,
+    ) -> io::Result<Self> {
+        let names = Count::new().columns(config).map(|(name, _)| name);
+        match config.format {
+            Format::Text => {}
+            Format::Json => write!(out, "{{\"files\":[")?,
+            Format::Csv | Format::Tsv => {
+                let separator = if config.format == Format::Csv {
+                    ","
+                } else {
+                    "\t"
+                };
+                let language = if config.code {
+                    ["language"].as_slice()
+                } else {
+                    &[]
+                };
+                let stats = if config.stats {
+                    STATS_FIELDS.as_slice()
+                } else {
+                    &[]
+                };
+                let header: Vec<_> = ["file"]
+                    .iter()
+                    .chain(language)
+                    .copied()
+                    .chain(names)
+                    .collect();
+                out.write_all(header.join(separator).as_bytes())?;
+                // patterns may hold anything, so they are escaped like paths
+                for name in match_names(config) {
+                    out.write_all(separator.as_bytes())?;
+                    if config.format == Format::Csv {
+                        write_csv_field(&mut out, name.as_bytes())?;
+                    } else {
+                        write_tsv_field(&mut out, name.as_bytes())?;
+                    }
+                }
+                for name in stats {
+                    write!(out, "{}{}", separator, name)?;
+                }
+                writeln!(out)?
+            }
+        }
+        let mut width = number_width(config, paths);
+        if config.group_digits {
+            width += (width - 1) / 3;
+        }
+        Ok(Report {
+            config,
+            out,
+            records: 0,
+            width,
+        })
+    }
+
+    /// Prints the counts of one input; stdin goes unnamed when no files were.
+    pub fn file(&mut self, count: &Count, path: Option<&Path>) -> io::Result<()> {
+        if self.config.format == Format::Json {
+            if self.records > 0 {
+                self.out.write_all(b",")?;
+            }
+            self.out.write_all(b"\n{\"file\":")?;
+            match path {
+                Some(path) => write_json_path(&mut self.out, path)?,
+                None => self.out.write_all(b"null")?,
+            }
+            if self.config.code {
+                self.out.write_all(b",")?;
+                self.write_json_language(count.language)?;
+            }
+            self.write_json_counts(count, true)?;
+            self.write_json_stats(count)?;
+            self.out.write_all(b"}")?;
+        } else {
+            let label = path.map(|path| path.as_os_str().as_bytes());
+            let language = self.config.code.then_some(count.language);
+            self.write_row(count, label, language)?;
+        }
+        self.records += 1;
+        Ok(())
+    }
+
+    /// Prints the totals, if there are any, and ends the report. With
+    /// --code, the total of each language comes before the grand total, and
+    /// with --top, the most frequent words come last.
+    pub fn finish(
+        mut self,
+        total: Option<&Count>,
+        by_language: &[(Option<Language>, Count)],
+        top_words: Option<&[(String, u64)]>,
+    ) -> io::Result<()> {
+        let by_language = if self.config.code && total.is_some() {
+            by_language
+        } else {
+            &[]
+        };
+        match (self.config.format, total) {
+            (Format::Json, total) => {
+                if self.records > 0 {
+                    self.out.write_all(b"\n")?;
+                }
+                self.out.write_all(b"]")?;
+                if !by_language.is_empty() {
+                    self.out.write_all(b",\"languages\":[")?;
+                    for (index, (language, count)) in by_language.iter().enumerate() {
+                        let comma = if index > 0 { "," } else { "" };
+                        write!(self.out, "{}\n{{", comma)?;
+                        self.write_json_language(*language)?;
+                        self.write_json_counts(count, true)?;
+                        self.write_json_stats(count)?;
+                        self.out.write_all(b"}")?;
+                    }
+                    self.out.write_all(b"\n]")?;
+                }
+                if let Some(total) = total {
+                    self.out.write_all(b",\"total\":{")?;
+                    self.write_json_counts(total, false)?;
+                    self.write_json_stats(total)?;
+                    self.out.write_all(b"}")?;
+                }
+                if let Some(top_words) = top_words {
+                    self.out.write_all(b",\"top_words\":[")?;
+                    for (index, (word, count)) in top_words.iter().enumerate() {
+                        let comma = if index > 0 { "," } else { "" };
+                        write!(self.out, "{}\n{{\"word\":", comma)?;
+                        write_json_string(&mut self.out, word.as_bytes())?;
+                        write!(self.out, ",\"count\":{}}}", count)?;
+                    }
+                    if !top_words.is_empty() {
+                        self.out.write_all(b"\n")?;
+                    }
+                    self.out.write_all(b"]")?;
+                }
+                self.out.write_all(b"}\n")?;
+            }
+            (format, Some(total)) => {
+                for (language, count) in by_language {
+                    self.write_row(count, Some(b"total"), Some(*language))?;
+                }
+                // the total goes unlabelled when it is all there is, as in GNU wc
+                let label = if format == Format::Text && self.config.total == TotalMode::Only {
+                    None
+                } else {
+                    Some(b"total".as_slice())
+                };
+                self.write_row(total, label, None)?;
+            }
+            (_, None) => {}
+        }
+        if self.config.format == Format::Text {
+            self.write_top_words(top_words.unwrap_or_default())?;
+        }
+        self.out.flush()
+    }
+
+    /// Prints the counts so far of the input being counted to stderr, in
+    /// text whatever the format, as BSD wc does on SIGINFO.
+    pub fn progress(&self, count: &Count, path: Option<&Path>) {
+        let mut row = Vec::new();
+        let label = path.map(|path| path.as_os_str().as_bytes());
+        let language = self.config.code.then_some(count.language);
+        // a failed report is no reason to stop counting
+        let _ = self.write_text_row(&mut row, count, label, language);
+        let _ = io::stderr().write_all(&row);
+    }
+
+    // With --code, rows of files and languages have a language, which may
+    // be unknown; the row of the grand total has none.
+    fn write_row(
+        &mut self,
+        count: &Count,
+        label: Option<&[u8]>,
+        language: Option<Option<Language>>,
+    ) -> io::Result<()> {
+        let stats = count.line_stats.as_ref();
+        // with nothing but --top, there is nothing to print of each input
+        if values(self.config, count).next().is_none() && stats.is_none() {
+            return Ok(());
+        }
+        if self.config.format == Format::Text {
+            let mut row = Vec::new();
+            self.write_text_row(&mut row, count, label, language)?;
+            if let Some(stats) = stats {
+                self.write_text_stats(&mut row, stats)?;
+            }
+            return self.out.write_all(&row);
+        }
+
+        let language = language.map(language_name);
+        let stats = stats
+            .filter(|_| self.config.stats)
+            .map(stats_fields)
+            .unwrap_or_default();
+        let values = values(self.config, count);
+        let out = &mut self.out;
+        match self.config.format {
+            Format::Csv => {
+                write_csv_field(out, label.unwrap_or_default())?;
+                if self.config.code {
+                    write!(out, ",{}", language.unwrap_or_default())?;
+                }
+                for value in values {
+                    write!(out, ",{}", value)?;
+                }
+                for field in stats {
+                    write!(out, ",{}", field)?;
+                }
+            }
+            Format::Tsv => {
+                write_tsv_field(out, label.unwrap_or_default())?;
+                if self.config.code {
+                    write!(out, "\t{}", language.unwrap_or_default())?;
+                }
+                for value in values {
+                    write!(out, "\t{}", value)?;
+                }
+                for field in stats {
+                    write!(out, "\t{}", field)?;
+                }
+            }
+            Format::Text | Format::Json => unreachable!("JSON has no rows"),
+        }
+        out.write_all(b"\n")
+    }
+
+    fn write_text_row(
+        &self,
+        out: &mut dyn Write,
+        count: &Count,
+        label: Option<&[u8]>,
+        language: Option<Option<Language>>,
+    ) -> io::Result<()> {
+        for (index, value) in values(self.config, count).enumerate() {
+            let value = if self.config.group_digits {
+                group_digits(value)
+            } else {
+                value.to_string()
+            };
+            let space = if index > 0 { " " } else { "" };
+            write!(out, "{}{:>2$}", space, value, self.width)?;
+        }
+        if let Some(label) = label {
+            out.write_all(b" ")?;
+            out.write_all(label)?;
+        }
+        if let Some(language) = language {
+            write!(out, " ({})", language_name(language))?;
+        }
+        out.write_all(b"\n")
+    }
+
+    // Prints each word after how often it comes up, as `uniq -c` does.
+    fn write_top_words(&mut self, top_words: &[(String, u64)]) -> io::Result<()> {
+        let counts: Vec<_> = top_words
+            .iter()
+            .map(|&(_, count)| {
+                if self.config.group_digits {
+                    group_digits(count)
+                } else {
+                    count.to_string()
+                }
+            })
+            .collect();
+        let width = counts.iter().map(String::len).max().unwrap_or(0);
+        for ((word, _), count) in top_words.iter().zip(counts) {
+            writeln!(self.out, "{:>2$} {}", count, word, width)?;
+        }
+        Ok(())
+    }
+
+    // Prints --stats and --histogram under a row, indented.
+    fn write_text_stats(&self, out: &mut dyn Write, stats: &LineStats) -> io::Result<()> {
+        let unit = self.config.length_unit;
+        if self.config.stats {
+            match (stats.min(), stats.max(), stats.mean()) {
+                (Some(min), Some(max), Some(mean)) => writeln!(
+                    out,
+                    "  line lengths in {}: min {}, max {}, mean {:.1}, median {}, p99 {}",
+                    unit,
+                    min,
+                    max,
+                    mean,
+                    stats.percentile(50).unwrap_or_default(),
+                    stats.percentile(99).unwrap_or_default(),
+                )?,
+                _ => writeln!(out, "  line lengths in {}: no lines", unit)?,
+            }
+            writeln!(out, "  empty lines: {}", stats.empty())?;
+
+            let longest_at = stats.longest_at();
+            if !longest_at.is_empty() {
+                let lines: Vec<_> = longest_at.iter().map(u64::to_string).collect();
+                write!(out, "  longest at lines {}", lines.join(", "))?;
+                let more = stats.longest() - longest_at.len() as u64;
+                if more > 0 {
+                    write!(out, " and {} more", more)?;
+                }
+                writeln!(out)?;
+            }
+        }
+
+        let Some(width) = self.config.histogram else {
+            return Ok(());
+        };
+        let buckets = stats.histogram(width);
+        let Some(most) = buckets.iter().map(|&(_, lines)| lines).max() else {
+            return Ok(());
+        };
+        writeln!(out, "  histogram of line lengths in {}:", unit)?;
+        let ranges: Vec<_> = buckets
+            .iter()
+            .map(|&(start, _)| match width {
+                1 => start.to_string(),
+                _ => format!("{}-{}", start, start + width - 1),
+            })
+            .collect();
+        let range_width = ranges.iter().map(String::len).max().unwrap_or(0);
+        let lines_width = most.to_string().len();
+        for (range, (_, lines)) in ranges.iter().zip(&buckets) {
+            // every bucket with lines in it gets a bar, however short
+            let bar = (lines * BAR_WIDTH).div_ceil(most) as usize;
+            writeln!(
+                out,
+                "  {:>3$} {:>4$} {}",
+                range,
+                lines,
+                "#".repeat(bar),
+                range_width,
+                lines_width
+            )?;
+        }
+        Ok(())
+    }
+
+    fn write_json_stats(&mut self, count: &Count) -> io::Result<()> {
+        let Some(stats) = &count.line_stats else {
+            return Ok(());
+        };
+        write!(
+            self.out,
+            ",\"line_lengths\":{{\"unit\":\"{}\",\"lines\":{}",
+            self.config.length_unit,
+            stats.lines()
+        )?;
+        if self.config.stats {
+            write!(self.out, ",\"empty\":{}", stats.empty())?;
+            for (name, value) in STATS_FIELDS[..5].iter().zip(stats_fields(stats)) {
+                let value = if value.is_empty() { "null" } else { &value };
+                let name = name.trim_end_matches("_length");
+                write!(self.out, ",\"{}\":{}", name, value)?;
+            }
+            let lines: Vec<_> = stats.longest_at().iter().map(u64::to_string).collect();
+            write!(self.out, ",\"longest_at\":[{}]", lines.join(","))?;
+        }
+        if let Some(width) = self.config.histogram {
+            self.out.write_all(b",\"histogram\":[")?;
+            for (index, (start, lines)) in stats.histogram(width).into_iter().enumerate() {
+                let comma = if index > 0 { "," } else { "" };
+                write!(
+                    self.out,
+                    "{}{{\"from\":{},\"to\":{},\"lines\":{}}}",
+                    comma,
+                    start,
+                    start + width - 1,
+                    lines
+                )?;
+            }
+            self.out.write_all(b"]")?;
+        }
+        self.out.write_all(b"}")
+    }
+
+    fn write_json_language(&mut self, language: Option<Language>) -> io::Result<()> {
+        match language {
+            Some(language) => write!(self.out, "\"language\":\"{}\"", language),
+            None => write!(self.out, "\"language\":null"),
+        }
+    }
+
+    fn write_json_counts(&mut self, count: &Count, after_file: bool) -> io::Result<()> {
+        let mut comma = if after_file { "," } else { "" };
+        for (name, value) in count.columns(self.config) {
+            write!(self.out, "{}\"{}\":{}", comma, name, value)?;
+            comma = ",";
+        }
+        if self.config.matcher.is_some() {
+            write!(self.out, "{}\"matches\":{{", comma)?;
+            let matches = match_names(self.config).zip(match_counts(self.config, count));
+            for (index, (name, matches)) in matches.enumerate() {
+                if index > 0 {
+                    self.out.write_all(b",")?;
+                }
+                write_json_string(&mut self.out, name.as_bytes())?;
+                write!(self.out, ":{}", matches)?;
+            }
+            self.out.write_all(b"}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The CSV and TSV columns of --stats, which stats_fields() fills in.
+const STATS_FIELDS: [&str; 6] = [
+    "min_length",
+    "max_length",
+    "mean_length",
+    "median_length",
+    "p99_length",
+    "empty_lines",
+];
+
+// The --stats columns of a CSV or TSV row, left empty without any lines.
+fn stats_fields(stats: &LineStats) -> Vec<String> {
+    let show = |value: Option<u64>| value.map(|value| value.to_string()).unwrap_or_default();
+    vec![
+        show(stats.min()),
+        show(stats.max()),
+        stats
+            .mean()
+            .map(|mean| format!("{:.2}", mean))
+            .unwrap_or_default(),
+        show(stats.percentile(50)),
+        show(stats.percentile(99)),
+        stats.empty().to_string(),
+    ]
+}
+
+// The -p and -F patterns, in the order their counts are printed in.
+fn match_names(config: &Config) -> impl Iterator<Item = &String> {
+    config.matcher.iter().flat_map(|matcher| matcher.names())
+}
+
+// The matches of each pattern, including those of no input at all.
+fn match_counts<'c>(config: &Config, count: &'c Count) -> impl Iterator<Item = u64> + 'c {
+    let patterns = match_names(config).count();
+    (0..patterns).map(|index| count.matches.get(index).copied().unwrap_or(0))
+}
+
+// Every value in a row: the counters in GNU wc's order, then the matches.
+fn values<'c>(config: &'c Config, count: &'c Count) -> impl Iterator<Item = u64> + 'c {
+    count
+        .columns(config)
+        .map(|(_, value)| value)
+        .chain(match_counts(config, count))
+}
+
+fn language_name(language: Option<Language>) -> String {
+    language.map_or_else(|| String::from("unknown"), |language| language.to_string())
+}
+
+/// How wide GNU wc makes each column: wide enough for the combined size
+/// of the regular files, and at least 7 if any input is something else, such
+/// as a pipe, whose size can't be known ahead. A lone counter of one input
+/// is printed as it is, and so is everything when the inputs aren't known.
+fn number_width<'p>(config: &Config, paths: Option<impl IntoIterator<Item = &'p Path>>) -> usize {
+    let Some(paths) = paths else {
+        return 1;
+    };
+    let paths: Vec<_> = paths.into_iter().collect();
+    if paths.is_empty() || (paths.len() == 1 && values(config, &Count::new()).count() == 1) {
+        return 1;
+    }
+
+    let mut minimum_width = 1;
+    let mut regular_total = 0;
+    // inputs that can't be looked at are left out
+    for metadata in paths.into_iter().filter_map(|path| metadata(path).ok()) {
+        if metadata.is_file() {
+            regular_total += metadata.len();
+        } else {
+            minimum_width = 7;
+        }
+    }
+    regular_total.to_string().len().max(minimum_width)
+}
+
+/// Looks up a file's metadata, `-` being stdin.
+pub fn metadata(path: &Path) -> io::Result<Metadata> {
+    if path == Path::new("-") {
+        File::from(io::stdin().as_fd().try_clone_to_owned()?).metadata()
+    } else {
+        fs::metadata(path)
+    }
+}
+
+// Separates thousands with commas, e.g. 1234567 as 1,234,567.
+fn group_digits(value: u64) -> String {
+    let digits = value.to_string();
+    let mut grouped = String::with_capacity(digits.len() * 4 / 3);
+    for (index, digit) in digits.chars().enumerate() {
+        if index > 0 && (digits.len() - index).is_multiple_of(3) {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+    grouped
+}
+
+// Quotes a field holding a delimiter, quote or line break, per RFC 4180.
+// CSV readers expect text, so bytes that aren't UTF-8 are written as \xNN,
+// and backslashes doubled to tell those apart from a name with \x in it.
+fn write_csv_field(out: &mut dyn Write, field: &[u8]) -> io::Result<()> {
+    let mut escaped = Vec::with_capacity(field.len());
+    for chunk in field.utf8_chunks() {
+        for part in chunk
+            .valid()
+            .as_bytes()
+            .split_inclusive(|&byte| byte == b'\\')
+        {
+            escaped.extend_from_slice(part);
+            if part.ends_with(b"\\") {
+                escaped.push(b'\\');
+            }
+        }
+        for &byte in chunk.invalid() {
+            write!(escaped, "\\x{:02x}", byte)?;
+        }
+    }
+    let field = &escaped[..];
+    if !field.iter().any(|byte| b",\"\r\n".contains(byte)) {
+        return out.write_all(field);
+    }
+    out.write_all(b"\"")?;
+    for part in field.split_inclusive(|&byte| byte == b'"') {
+        out.write_all(part)?;
+        if part.ends_with(b"\"") {
+            out.write_all(b"\"")?;
+        }
+    }
+    out.write_all(b"\"")
+}
+
+// TSV fields can't hold tabs or line breaks, so those and backslashes are
+// escaped with a backslash, as PostgreSQL and most TSV readers expect, and
+// so are bytes that aren't UTF-8, as \xNN.
+fn write_tsv_field(out: &mut dyn Write, field: &[u8]) -> io::Result<()> {
+    for chunk in field.utf8_chunks() {
+        for &byte in chunk.valid().as_bytes() {
+            match byte {
+                b'\t' => out.write_all(b"\\t")?,
+                b'\n' => out.write_all(b"\\n")?,
+                b'\r' => out.write_all(b"\\r")?,
+                b'\\' => out.write_all(b"\\\\")?,
+                _ => out.write_all(&[byte])?,
+            }
+        }
+        for &byte in chunk.invalid() {
+            write!(out, "\\x{:02x}", byte)?;
+        }
+    }
+    Ok(())
+}
+
+fn write_json_path(out: &mut dyn Write, path: &Path) -> io::Result<()> {
+    write_json_string(out, path.as_os_str().as_bytes())
+}
+
+// Writes bytes as a JSON string. JSON can only hold Unicode, so a byte
+// that isn't UTF-8 becomes a lone surrogate escape, \udc80 to \udcff, the
+// same way Python's surrogateescape handler and os.fsdecode() round-trip it.
+fn write_json_string(out: &mut dyn Write, string: &[u8]) -> io::Result<()> {
+    out.write_all(b"\"")?;
+    for chunk in string.utf8_chunks() {
+        for char in chunk.valid().chars() {
+            match char {
+                '"' => out.write_all(b"\\\"")?,
+                '\\' => out.write_all(b"\\\\")?,
+                '\n' => out.write_all(b"\\n")?,
+                '\r' => out.write_all(b"\\r")?,
+                '\t' => out.write_all(b"\\t")?,
+                _ if (char as u32) < 0x20 => write!(out, "\\u{:04x}", char as u32)?,
+                _ => write!(out, "{}", char)?,
+            }
+        }
+        for &byte in chunk.invalid() {
+            write!(out, "\\udc{:02x}", byte)?;
+        }
+    }
+    out.write_all(b"\"")
+}
@@ -1,149 +1,705 @@
+use code::Language;
+use counter::Needs;
+use format::{Format, Report};
+use matches::{Matcher, Pattern};
+use regex::bytes::Regex;
+use stats::{LengthUnit, LineStats};
 use std::error::Error;
+use std::ffi::OsStr;
 use std::fs::File;
-use std::io::{self, BufRead, BufReader, Read};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
 use std::ops;
-use std::path::PathBuf;
+use std::os::fd::AsFd;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::thread;
+use structopt::clap;
 use structopt::StructOpt;
+use top::{Tokenizer, TopWords};
+
+mod code;
+mod counter;
+mod format;
+mod matches;
+mod parallel;
+mod progress;
+mod stats;
+mod top;
+
+pub use counter::{Counter, Counts};
 
 type FnResult<T> = Result<T, Box<dyn Error>>;
 
+const BUF_SIZE: usize = 256 * 1024;
+// the largest --files0-from list whose files GNU wc looks at before counting
+const MAX_LIST_SIZE: u64 = 10 * 1024 * 1024;
+
 #[derive(Debug, StructOpt)]
 #[structopt(author, about)]
 pub struct Config {
-    #[structopt(short = "c", help = "Count bytes", overrides_with = "chars")]
+    #[structopt(short = "c", help = "Count bytes")]
     bytes: bool,
-    #[structopt(short = "m", help = "Count UTF-8 chars", overrides_with = "bytes")]
+    #[structopt(short = "m", help = "Count UTF-8 chars")]
     chars: bool,
+    #[structopt(
+        long = "graphemes",
+        help = "Count user-perceived characters (UAX #29 extended grapheme clusters)"
+    )]
+    graphemes: bool,
     #[structopt(short = "w", help = "Count words")]
     words: bool,
+    #[structopt(
+        long = "words",
+        help = "How words are split: \"whitespace\" like GNU wc, or \"unicode\" (UAX #29)",
+        name = "MODE",
+        default_value = "whitespace",
+        possible_values = &["whitespace", "unicode"]
+    )]
+    word_mode: WordMode,
     #[structopt(short = "l", help = "Count lines")]
     lines: bool,
+    #[structopt(short = "L", help = "Print the display width of the longest line")]
+    max_line_length: bool,
+    #[structopt(
+        long = "code",
+        help = "Count lines of code, comments and blanks, in Rust, C, Python, shell or TOML"
+    )]
+    code: bool,
+    #[structopt(
+        short = "p",
+        help = "Count matches of the regex PATTERN; may be given more than once",
+        name = "PATTERN",
+        number_of_values = 1
+    )]
+    patterns: Vec<Regex>,
+    #[structopt(
+        short = "F",
+        help = "Count occurrences of STRING; may be given more than once",
+        name = "STRING",
+        number_of_values = 1
+    )]
+    strings: Vec<String>,
+    #[structopt(
+        long = "overlapping",
+        help = "Count matches of -p and -F that overlap others of the same pattern"
+    )]
+    overlapping: bool,
+    #[structopt(
+        long = "stats",
+        help = "Print the shortest, longest, mean, median and 99th percentile line lengths"
+    )]
+    stats: bool,
+    #[structopt(
+        long = "histogram",
+        help = "Print how many lines have lengths in each bucket WIDTH long",
+        name = "WIDTH"
+    )]
+    histogram: Option<u64>,
+    #[structopt(
+        long = "length-unit",
+        help = "What --stats and --histogram measure lines in",
+        name = "UNIT",
+        default_value = "bytes",
+        possible_values = &["bytes", "columns"]
+    )]
+    length_unit: LengthUnit,
+    #[structopt(
+        long = "top",
+        help = "Print the NUM most frequent words of all inputs, with how often each comes up; \
+                words are counted by their first 256 bytes",
+        name = "NUM"
+    )]
+    top: Option<usize>,
+    #[structopt(
+        long = "ignore-case",
+        help = "Count words in any case as one for --top"
+    )]
+    ignore_case: bool,
+    #[structopt(
+        long = "tokens",
+        help = "How --top splits words: at whitespace, at Unicode (UAX #29) word boundaries, \
+                or into identifiers of letters, digits and underscores",
+        name = "TOKENIZER",
+        default_value = "whitespace",
+        possible_values = &["whitespace", "unicode", "identifier"]
+    )]
+    tokenizer: Tokenizer,
+    #[structopt(
+        long = "stopwords",
+        parse(from_os_str),
+        help = "Leave the words in STOPWORDS out of --top",
+        name = "STOPWORDS"
+    )]
+    stopwords: Option<PathBuf>,
+    #[structopt(
+        long = "top-capacity",
+        help = "How many different words --top keeps count of before its counts turn approximate",
+        name = "WORDS",
+        default_value = "1000000"
+    )]
+    top_capacity: usize,
+    #[structopt(
+        long = "threads",
+        help = "Threads to count each large file with [default: one per CPU]",
+        name = "N"
+    )]
+    threads: Option<usize>,
+    #[structopt(
+        long = "total",
+        help = "When to print a line of totals",
+        name = "WHEN",
+        default_value = "auto",
+        possible_values = &["auto", "always", "only", "never"]
+    )]
+    total: TotalMode,
+    #[structopt(
+        long = "group-digits",
+        help = "Separate thousands with commas in text output"
+    )]
+    group_digits: bool,
+    #[structopt(
+        long = "format",
+        help = "Output format; json, csv and tsv name each field after its counter \
+                and escape bytes that aren't UTF-8, csv and tsv as \\xNN and a backslash as \\\\",
+        name = "FORMAT",
+        default_value = "text",
+        possible_values = &["text", "json", "csv", "tsv"]
+    )]
+    format: Format,
+    #[structopt(
+        long = "files0-from",
+        parse(from_os_str),
+        help = "Read input file names from F, each ended by a NUL; - reads them from stdin",
+        name = "F",
+        conflicts_with = "FILE"
+    )]
+    files0_from: Option<PathBuf>,
+    #[structopt(
+        long = "passthrough",
+        help = "Copy stdin to stdout as it is counted, printing the counts to stderr",
+        conflicts_with_all = &["FILE", "F"]
+    )]
+    passthrough: bool,
+    #[structopt(
+        long = "report",
+        parse(from_os_str),
+        help = "Write the counts to PATH instead",
+        name = "PATH"
+    )]
+    report: Option<PathBuf>,
     #[structopt(parse(from_os_str), name = "FILE", help = "Input file(s)")]
     files: Vec<PathBuf>,
+    // the -p and -F patterns, compiled together
+    #[structopt(skip)]
+    matcher: Option<Arc<Matcher>>,
+    // the words of every input, with --top
+    #[structopt(skip)]
+    top_words: Option<Arc<TopWords>>,
 }
 
+#[allow(clippy::new_without_default)]
 impl Config {
     pub fn new() -> Self {
-        Config::from_args()
+        let matches = Config::clap().get_matches();
+        let mut config = Config::from_clap(&matches);
+        if !(config.patterns.is_empty() && config.strings.is_empty()) {
+            // the columns of -p and -F go in the order they were given in
+            let mut patterns: Vec<(usize, Pattern)> = Vec::new();
+            if let Some(indices) = matches.indices_of("PATTERN") {
+                patterns.extend(indices.zip(config.patterns.iter().map(Pattern::Regex)));
+            }
+            if let Some(indices) = matches.indices_of("STRING") {
+                patterns.extend(indices.zip(config.strings.iter().map(|s| Pattern::String(s))));
+            }
+            patterns.sort_by_key(|&(index, _)| index);
+            let patterns: Vec<Pattern> = patterns.into_iter().map(|(_, pattern)| pattern).collect();
+            match Matcher::new(&patterns, config.overlapping) {
+                Ok(matcher) => config.matcher = Some(Arc::new(matcher)),
+                Err(e) => {
+                    clap::Error::with_description(&e.to_string(), clap::ErrorKind::InvalidValue)
+                        .exit()
+                }
+            }
+        }
+        if config.top.is_some() {
+            let stopwords = config.stopwords.as_deref();
+            let capacity = config.top_capacity;
+            match TopWords::new(config.tokenizer, config.ignore_case, stopwords, capacity) {
+                Ok(top_words) => config.top_words = Some(Arc::new(top_words)),
+                // only reading the stopwords can fail
+                Err(e) => {
+                    let message = format!("{}: {}", stopwords.unwrap().display(), e);
+                    clap::Error::with_description(&message, clap::ErrorKind::Io).exit()
+                }
+            }
+        }
+        config
+    }
+
+    // with no column flags, GNU wc's default columns are printed
+    fn no_args(&self) -> bool {
+        !(self.bytes
+            || self.chars
+            || self.graphemes
+            || self.words
+            || self.lines
+            || self.max_line_length
+            || self.code
+            || self.matcher.is_some()
+            || self.top_words.is_some())
+    }
+
+    fn needs(&self) -> Needs {
+        Needs {
+            words: self.no_args() || self.words,
+            chars: self.chars,
+            graphemes: self.graphemes,
+            max_line_length: self.max_line_length,
+            code: self.code,
+            line_lengths: self.line_lengths(),
+        }
+    }
+
+    // lines are only measured for --stats and --histogram
+    fn line_lengths(&self) -> Option<LengthUnit> {
+        (self.stats || self.histogram.is_some()).then_some(self.length_unit)
+    }
+
+    fn bytes_only(&self) -> bool {
+        self.bytes
+            && !(self.chars
+                || self.graphemes
+                || self.words
+                || self.lines
+                || self.max_line_length
+                || self.code)
+            && self.line_lengths().is_none()
+            && self.matcher.is_none()
+            && self.top_words.is_none()
+    }
+
+    fn threads(&self) -> usize {
+        self.threads
+            .or_else(|| thread::available_parallelism().ok().map(usize::from))
+            .unwrap_or(1)
     }
 }
 
-#[derive(Debug)]
-// (lines, words, bytes/chars)
-struct Count(u64, u64, u64);
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum WordMode {
+    // words are runs of printable characters between whitespace
+    Whitespace,
+    // words are UAX #29 word segments containing a letter or digit
+    Unicode,
+}
+
+impl FromStr for WordMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "whitespace" => Ok(WordMode::Whitespace),
+            "unicode" => Ok(WordMode::Unicode),
+            _ => Err(format!("unknown word mode '{}'", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum TotalMode {
+    // only when there is more than one input
+    Auto,
+    Always,
+    // the total and nothing else, without a label
+    Only,
+    Never,
+}
+
+impl FromStr for TotalMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(TotalMode::Auto),
+            "always" => Ok(TotalMode::Always),
+            "only" => Ok(TotalMode::Only),
+            "never" => Ok(TotalMode::Never),
+            _ => Err(format!("unknown total mode '{}'", s)),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+struct Count {
+    lines: u64,
+    words: u64,
+    chars: u64,
+    graphemes: u64,
+    bytes: u64,
+    max_line_length: u64,
+    code: u64,
+    comments: u64,
+    blanks: u64,
+    // what the lines of a single input were sorted into code and comments as
+    language: Option<Language>,
+    // with --stats or --histogram
+    line_stats: Option<LineStats>,
+    // of each -p and -F pattern, in the order of Matcher::names()
+    matches: Vec<u64>,
+}
 
 impl Count {
     pub fn new() -> Self {
-        Count(0, 0, 0)
+        Count::default()
     }
 
-    pub fn count_from_bytes(input: &mut dyn BufRead) -> Self {
-        let mut count = Self::new();
-        let mut in_word = true;
-        for byte in input.bytes() {
-            let byte = byte.unwrap();
-            if byte == '\n' as u8 {
-                count.0 += 1;
+    /// Counts what `needs` asks for in a single pass, telling code from
+    /// comments by the language of `path`, or failing that, its `#!` line.
+    ///
+    /// On a read error, whatever was counted before it is kept, since GNU wc
+    /// still prints those counts after the diagnostic. The counts so far go
+    /// to `progress` whenever a progress report is asked for.
+    pub fn read_from(
+        &mut self,
+        input: &mut dyn BufRead,
+        path: &Path,
+        config: &Config,
+        progress: &dyn Fn(&Count),
+    ) -> io::Result<()> {
+        let needs = config.needs();
+        let mut counter = Counter::with_needs(config.word_mode, needs);
+        if needs.code {
+            let language = match Language::from_path(path) {
+                Some(language) => Some(language),
+                None => Language::from_shebang(input.fill_buf()?),
+            };
+            counter.count_code(language);
+            self.language = language;
+        }
+        if let Some(matcher) = &config.matcher {
+            counter.count_matches(Arc::clone(matcher));
+        }
+        if let Some(top_words) = &config.top_words {
+            counter.count_top_words(Arc::clone(top_words));
+        }
+        let result = loop {
+            if progress::requested() {
+                progress(&counter.snapshot());
             }
-            if byte.is_ascii_whitespace() {
-                if in_word {
-                    count.1 += 1;
-                    in_word = false;
+            match input.fill_buf() {
+                Ok([]) => break Ok(()),
+                Ok(buf) => {
+                    let len = buf.len();
+                    counter.update(buf);
+                    input.consume(len);
                 }
-            } else if !in_word {
-                in_word = true;
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => break Err(e),
             }
-            count.2 += 1;
-        }
-        count
+        };
+        *self += counter.into_count();
+        result
     }
 
-    pub fn count_from_chars(input: &mut dyn BufRead) -> Self {
-        let mut count = Self::new();
-        let mut buf = String::new();
-        while input.read_line(&mut buf).unwrap() != 0 {
-            for char in buf.chars() {
-                if char == '\n' {
-                    count.0 += 1;
-                }
-                if char.is_whitespace() {
-                    count.1 += 1;
-                }
-                count.2 += 1;
+    /// Counts a file from where it is, splitting it across threads if it is
+    /// large enough, or taking its size on trust if bytes are all that count.
+    fn read_file(
+        &mut self,
+        mut file: File,
+        path: &Path,
+        config: &Config,
+        progress: &dyn Fn(&Count),
+    ) -> io::Result<()> {
+        let metadata = file.metadata()?;
+        let threads = config.threads();
+        if config.bytes_only() && metadata.is_file() {
+            // files in /proc and the like claim sizes of 0 or a whole page,
+            // so as GNU wc does, only the last block of those gets read
+            let (start, size) = (file.stream_position()?, metadata.len());
+            if size % page_size() != 0 {
+                self.bytes += size.saturating_sub(start);
+                return Ok(());
+            }
+            let skip_to = size - size % (metadata.blksize() + 1);
+            if start < skip_to {
+                file.seek(SeekFrom::Start(skip_to))?;
+                self.bytes += skip_to - start;
             }
-            buf.clear();
+        } else if threads > 1
+            && !config.code
+            && config.line_lengths().is_none()
+            && config.matcher.is_none()
+            && config.top_words.is_none()
+            && metadata.is_file()
+            && metadata.len() >= parallel::MIN_SIZE
+        {
+            let size = metadata.len();
+            *self += parallel::count_file(&file, size, threads, config, progress)?;
+            return Ok(());
         }
-        count
+        let mut read_stream = BufReader::with_capacity(BUF_SIZE, file);
+        self.read_from(&mut read_stream, path, config, progress)
     }
 
-    pub fn print(&self, config: &Config, label: Option<String>) {
-        let no_args = !(config.bytes || config.chars || config.words || config.lines);
-
-        if no_args || config.lines {
-            print!("{:8}", self.0);
-        }
-        if no_args || config.words {
-            print!("{:8}", self.1);
-        }
-        if no_args || config.chars || config.bytes {
-            print!("{:8}", self.2);
-        }
-        if let Some(label) = label {
-            println!(" {}", label);
-        } else {
-            println!();
-        }
+    /// The columns to print, named and in GNU wc's order, whatever order
+    /// the flags were in.
+    fn columns(&self, config: &Config) -> impl Iterator<Item = (&'static str, u64)> {
+        let no_args = config.no_args();
+        [
+            (no_args || config.lines, "lines", self.lines),
+            (no_args || config.words, "words", self.words),
+            (config.chars, "chars", self.chars),
+            (config.graphemes, "graphemes", self.graphemes),
+            (no_args || config.bytes, "bytes", self.bytes),
+            (
+                config.max_line_length,
+                "max_line_length",
+                self.max_line_length,
+            ),
+            (config.code, "code", self.code),
+            (config.code, "comments", self.comments),
+            (config.code, "blanks", self.blanks),
+        ]
+        .into_iter()
+        .filter(|(shown, _, _)| *shown)
+        .map(|(_, name, value)| (name, value))
     }
 }
 
 impl ops::Add<Count> for Count {
     type Output = Self;
 
-    fn add(self, other: Count) -> Self::Output {
-        Self(self.0 + other.0, self.1 + other.1, self.2 + other.2)
+    fn add(mut self, other: Count) -> Self::Output {
+        self += other;
+        self
     }
 }
 
 impl ops::AddAssign<Count> for Count {
     fn add_assign(&mut self, other: Count) {
-        self.0 += other.0;
-        self.1 += other.1;
-        self.2 += other.2;
+        *self += &other;
     }
 }
 
-pub fn run(config: &Config) -> FnResult<()> {
-    let get_count: Box<dyn Fn(&mut dyn BufRead) -> Count> = match config.chars {
-        true => Box::new(Count::count_from_chars),
-        false => Box::new(Count::count_from_bytes),
-    };
+impl ops::AddAssign<&Count> for Count {
+    fn add_assign(&mut self, other: &Count) {
+        self.lines += other.lines;
+        self.words += other.words;
+        self.chars += other.chars;
+        self.graphemes += other.graphemes;
+        self.bytes += other.bytes;
+        self.max_line_length = self.max_line_length.max(other.max_line_length);
+        self.code += other.code;
+        self.comments += other.comments;
+        self.blanks += other.blanks;
+        if self.matches.len() < other.matches.len() {
+            self.matches.resize(other.matches.len(), 0);
+        }
+        for (matches, other_matches) in self.matches.iter_mut().zip(&other.matches) {
+            *matches += other_matches;
+        }
+        if let Some(other_stats) = &other.line_stats {
+            match &mut self.line_stats {
+                Some(stats) => stats.merge(other_stats),
+                None => self.line_stats = Some(other_stats.clone()),
+            }
+        }
+    }
+}
+
+fn page_size() -> u64 {
+    match unsafe { libc::sysconf(libc::_SC_PAGESIZE) } {
+        size if size > 0 => size as u64,
+        _ => 4096,
+    }
+}
+
+/// Copies whatever is read through it to `out`, for --passthrough.
+struct Tee<R, W> {
+    input: R,
+    out: W,
+}
 
-    if config.files.len() == 0 {
-        let mut read_stream = BufReader::new(io::stdin());
-        let count = get_count(&mut read_stream);
-        count.print(config, None);
+impl<R: Read, W: Write> Read for Tee<R, W> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.input.read(buf)?;
+        // flushed as it goes, so the next command in a pipeline isn't kept
+        // waiting on a buffer
+        self.out
+            .write_all(&buf[..len])
+            .and_then(|()| self.out.flush())
+            .map_err(|e| io::Error::new(e.kind(), format!("write error: {}", e)))?;
+        Ok(len)
+    }
+}
+
+/// Reads the NUL separated file names of --files0-from, returning an error
+/// message in place of each name that can't be used.
+fn read_file_names(list: &Path) -> FnResult<Vec<Result<PathBuf, String>>> {
+    let from_stdin = list == Path::new("-");
+    let mut names = Vec::new();
+    if from_stdin {
+        io::stdin().read_to_end(&mut names)?;
     } else {
-        let mut total_count = Count::new();
+        File::open(list)
+            .and_then(|mut file| file.read_to_end(&mut names))
+            .map_err(|e| format!("cannot open '{}' for reading: {}", list.display(), e))?;
+    }
 
-        for path in &config.files {
-            let file = match File::open(path) {
-                Ok(file) => file,
+    // the last name may go without its NUL
+    if names.last() == Some(&b'\0') {
+        names.pop();
+    }
+    if names.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let names = names
+        .split(|&byte| byte == b'\0')
+        .enumerate()
+        .map(|(index, name)| {
+            if name.is_empty() {
+                Err(format!(
+                    "{}:{}: invalid zero-length file name",
+                    list.display(),
+                    index + 1
+                ))
+            } else if from_stdin && name == b"-" {
+                Err(String::from(
+                    "when reading file names from stdin, no file name of '-' allowed",
+                ))
+            } else {
+                Ok(PathBuf::from(OsStr::from_bytes(name)))
+            }
+        });
+    Ok(names.collect())
+}
+
+/// Runs rwc, returning the exit status: 1 if any input could not be read.
+pub fn run(config: &Config) -> FnResult<i32> {
+    let mut status = 0;
+    if config.histogram == Some(0) {
+        return Err("histogram buckets must be at least 1 wide".into());
+    }
+    if config.histogram.is_some() && matches!(config.format, Format::Csv | Format::Tsv) {
+        return Err("a histogram can't be printed as CSV or TSV".into());
+    }
+    if config.top.is_some() && matches!(config.format, Format::Csv | Format::Tsv) {
+        return Err("the most frequent words can't be printed as CSV or TSV".into());
+    }
+    progress::install();
+
+    // with no file names at all, stdin is counted and goes unlabelled
+    let unnamed_stdin = config.files.is_empty() && config.files0_from.is_none();
+    let names = match &config.files0_from {
+        Some(list) => read_file_names(list)?,
+        None if unnamed_stdin => vec![Ok(PathBuf::from("-"))],
+        None => config.files.iter().cloned().map(Ok).collect(),
+    };
+
+    // GNU wc only sizes its columns from a file list it could read ahead
+    let list_read_ahead = config.files0_from.as_deref().is_none_or(|list| {
+        format::metadata(list)
+            .is_ok_and(|metadata| metadata.is_file() && metadata.len() <= MAX_LIST_SIZE)
+    });
+    let paths = names.iter().filter_map(|name| name.as_deref().ok());
+    // stdout is taken by the input with --passthrough
+    let out: Box<dyn Write> = match &config.report {
+        Some(path) => match File::create(path) {
+            Ok(file) => Box::new(BufWriter::new(file)),
+            Err(e) => return Err(format!("{}: {}", path.display(), e).into()),
+        },
+        None if config.passthrough => Box::new(io::stderr()),
+        None => Box::new(io::stdout().lock()),
+    };
+    let mut report = Report::start(config, out, list_read_ahead.then_some(paths))?;
+    let mut total_count = Count::new();
+    let mut by_language: Vec<(Option<Language>, Count)> = Vec::new();
+    for name in &names {
+        let path = match name {
+            Ok(path) => path,
+            Err(message) => {
+                eprintln!("rwc: {}", message);
+                status = 1;
+                continue;
+            }
+        };
+
+        let label = (!unnamed_stdin).then_some(path.as_path());
+        let progress = |count: &Count| report.progress(count, label);
+        let mut count = Count::new();
+        let result = if config.passthrough {
+            let tee = Tee {
+                input: io::stdin().lock(),
+                out: io::stdout().lock(),
+            };
+            let mut read_stream = BufReader::with_capacity(BUF_SIZE, tee);
+            count.read_from(&mut read_stream, path, config, &progress)
+        } else if path == Path::new("-") && config.bytes_only() {
+            io::stdin()
+                .as_fd()
+                .try_clone_to_owned()
+                .and_then(|stdin| count.read_file(File::from(stdin), path, config, &progress))
+        } else if path == Path::new("-") {
+            let mut read_stream = BufReader::with_capacity(BUF_SIZE, io::stdin());
+            count.read_from(&mut read_stream, path, config, &progress)
+        } else {
+            match File::open(path) {
+                Ok(file) => count.read_file(file, path, config, &progress),
                 Err(e) => {
-                    eprintln!("rwc: {}: {}", path.to_str().unwrap(), e);
+                    eprintln!("rwc: {}: {}", path.display(), e);
+                    status = 1;
                     continue;
                 }
+            }
+        };
+        if let Err(e) = result {
+            let name = if path == Path::new("-") {
+                String::from("standard input")
+            } else {
+                path.display().to_string()
             };
-
-            let mut read_stream = BufReader::new(file);
-            let count = get_count(&mut read_stream);
-            count.print(config, Some(String::from(path.to_str().unwrap())));
-            total_count += count;
+            eprintln!("rwc: {}: {}", name, e);
+            status = 1;
         }
-
-        if config.files.len() > 1 {
-            total_count.print(config, Some(String::from("total")));
+        if config.total != TotalMode::Only {
+            report.file(&count, label)?;
         }
+        total_count += &count;
+        if config.code {
+            match by_language
+                .iter_mut()
+                .find(|(language, _)| *language == count.language)
+            {
+                Some((_, language_count)) => *language_count += count,
+                None => by_language.push((count.language, count)),
+            }
+        }
+    }
+    // files in no language we know come last
+    by_language.sort_by_key(|(language, _)| (language.is_none(), *language));
+
+    let print_total = match config.total {
+        TotalMode::Auto => names.len() > 1,
+        TotalMode::Always | TotalMode::Only => true,
+        TotalMode::Never => false,
+    };
+    let top_words = config
+        .top_words
+        .as_ref()
+        .zip(config.top)
+        .map(|(top_words, n)| top_words.top(n));
+    let top = top_words.as_ref().map(|(words, _)| words.as_slice());
+    report.finish(print_total.then_some(&total_count), &by_language, top)?;
+    if let Some((_, max_error)) = top_words.filter(|&(_, max_error)| max_error > 0) {
+        eprintln!(
+            "rwc: more than {} different words; the counts of the most frequent may be up to {} too low",
+            config.top_capacity, max_error
+        );
     }
 
-    Ok(())
+    Ok(status)
 }
@@ -2,8 +2,11 @@ use rwc::Config;
 use std::process;
 
 fn main() {
-    if let Err(e) = rwc::run(&Config::new()) {
-        eprintln!("rwc: {}", e);
-        process::exit(1);
+    match rwc::run(&Config::new()) {
+        Ok(status) => process::exit(status),
+        Err(e) => {
+            eprintln!("rwc: {}", e);
+            process::exit(1);
+        }
     }
 }
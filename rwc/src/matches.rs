@@ -0,0 +1,126 @@
+use aho_corasick::AhoCorasick;
+use regex::bytes::{Regex, RegexSet};
+use std::error::Error;
+
+/// A -p regex or -F fixed string.
+#[derive(Debug, Clone, Copy)]
+pub enum Pattern<'a> {
+    Regex(&'a Regex),
+    String(&'a str),
+}
+
+/// Counts matches of -p regexes and -F fixed strings, line by line.
+///
+/// The strings are all looked for at once with one Aho-Corasick automaton.
+/// The regexes are first run together as a set, which says which of them
+/// match a line at all, so a line none of them match is only scanned once.
+#[derive(Debug)]
+pub struct Matcher {
+    regexes: Vec<Regex>,
+    regex_set: RegexSet,
+    strings: Option<AhoCorasick>,
+    // where the counts of each regex and each string go
+    regex_columns: Vec<usize>,
+    string_columns: Vec<usize>,
+    overlapping: bool,
+    // the patterns, as given
+    names: Vec<String>,
+}
+
+impl Matcher {
+    /// Counts of the patterns go in the order they are given in.
+    pub fn new(patterns: &[Pattern], overlapping: bool) -> Result<Self, Box<dyn Error>> {
+        let (mut regexes, mut regex_columns) = (Vec::new(), Vec::new());
+        let (mut strings, mut string_columns) = (Vec::new(), Vec::new());
+        for (column, pattern) in patterns.iter().enumerate() {
+            match *pattern {
+                Pattern::Regex(regex) => {
+                    regexes.push(regex.clone());
+                    regex_columns.push(column);
+                }
+                Pattern::String(string) => {
+                    strings.push(string);
+                    string_columns.push(column);
+                }
+            }
+        }
+        let regex_set = RegexSet::new(regexes.iter().map(Regex::as_str))?;
+        let strings_matcher = if strings.is_empty() {
+            None
+        } else {
+            Some(AhoCorasick::new(&strings)?)
+        };
+        let names = patterns
+            .iter()
+            .map(|pattern| match pattern {
+                Pattern::Regex(regex) => regex.as_str().to_string(),
+                Pattern::String(string) => string.to_string(),
+            })
+            .collect();
+        Ok(Matcher {
+            regexes,
+            regex_set,
+            strings: strings_matcher,
+            regex_columns,
+            string_columns,
+            overlapping,
+            names,
+        })
+    }
+
+    /// The patterns, in the order their counts go in.
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// Adds the matches in a line, without its line break, to `counts`.
+    /// Each pattern is counted as if it were looked for on its own, so
+    /// matches of different patterns may overlap even when those of the
+    /// same pattern don't. `ends` is room to keep track of that in.
+    pub fn count(&self, line: &[u8], counts: &mut [u64], ends: &mut Vec<usize>) {
+        if !self.regexes.is_empty() {
+            for index in self.regex_set.matches(line).iter() {
+                counts[self.regex_columns[index]] += self.count_regex(&self.regexes[index], line);
+            }
+        }
+
+        let Some(strings) = &self.strings else {
+            return;
+        };
+        let columns = &self.string_columns;
+        if self.overlapping {
+            for found in strings.find_overlapping_iter(line) {
+                counts[columns[found.pattern().as_usize()]] += 1;
+            }
+            return;
+        }
+        // matches come in order of where they end, so a match of a string
+        // counts unless it starts before the last one of it ended
+        ends.clear();
+        ends.resize(columns.len(), 0);
+        for found in strings.find_overlapping_iter(line) {
+            let pattern = found.pattern().as_usize();
+            if found.start() >= ends[pattern] {
+                counts[columns[pattern]] += 1;
+                ends[pattern] = found.end();
+            }
+        }
+    }
+
+    fn count_regex(&self, regex: &Regex, line: &[u8]) -> u64 {
+        if !self.overlapping {
+            return regex.find_iter(line).count() as u64;
+        }
+        // overlapping matches are those starting anywhere after the start
+        // of the last one
+        let (mut count, mut start) = (0, 0);
+        while start <= line.len() {
+            let Some(found) = regex.find_at(line, start) else {
+                break;
+            };
+            count += 1;
+            start = found.start() + 1;
+        }
+        count
+    }
+}
@@ -0,0 +1,152 @@
+use crate::counter::{self, Counter, Needs};
+use crate::{progress, Config, Count, WordMode};
+use std::fs::File;
+use std::io;
+use std::os::unix::fs::FileExt;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+/// Files smaller than this are counted on one thread.
+pub const MIN_SIZE: u64 = 16 * 1024 * 1024;
+
+const BUF_SIZE: usize = 1024 * 1024;
+// how far past an even split to look for somewhere to split instead
+const SPLIT_WINDOW: usize = 64 * 1024;
+// how often to check whether a progress report was asked for
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Counts a regular file of `size` bytes, splitting it into up to `threads`
+/// stretches that are counted at once and stitched back together.
+///
+/// Progress reports add up what each thread has counted so far, without
+/// stitching, so a word split between threads may show up twice.
+pub fn count_file(
+    file: &File,
+    size: u64,
+    threads: usize,
+    config: &Config,
+    progress: &dyn Fn(&Count),
+) -> io::Result<Count> {
+    let (word_mode, needs) = (config.word_mode, config.needs());
+    let bounds = split_points(file, size, threads, word_mode, needs)?;
+    let snapshots: Vec<_> = bounds
+        .windows(2)
+        .map(|_| Mutex::new(Count::new()))
+        .collect();
+    let stretches = thread::scope(|scope| {
+        let counting = thread::current();
+        let workers: Vec<_> = bounds
+            .windows(2)
+            .zip(&snapshots)
+            .map(|(bounds, snapshot)| {
+                let (start, end) = (bounds[0], bounds[1]);
+                let counting = counting.clone();
+                scope.spawn(move || {
+                    let stretch = count_stretch(file, start, end, word_mode, needs, snapshot);
+                    counting.unpark();
+                    stretch
+                })
+            })
+            .collect();
+
+        while !workers.iter().all(|worker| worker.is_finished()) {
+            if progress::requested() {
+                let mut count = Count::new();
+                for snapshot in &snapshots {
+                    count += &*snapshot.lock().unwrap();
+                }
+                progress(&count);
+            }
+            thread::park_timeout(PROGRESS_INTERVAL);
+        }
+        workers
+            .into_iter()
+            .map(|worker| worker.join().unwrap())
+            .collect::<io::Result<Vec<_>>>()
+    })?;
+    Ok(counter::stitch(stretches))
+}
+
+// Picks where each stretch starts, from 0 through to `size`. Splitting
+// just after a newline leaves nothing to stitch but words; without a
+// newline nearby, a character boundary will do when only whitespace words
+// span it, and otherwise the stretch is merged into the next one.
+fn split_points(
+    file: &File,
+    size: u64,
+    threads: usize,
+    word_mode: WordMode,
+    needs: Needs,
+) -> io::Result<Vec<u64>> {
+    let mut points = vec![0];
+    let mut window = vec![0; SPLIT_WINDOW];
+    for part in 1..threads as u64 {
+        let target = size / threads as u64 * part;
+        if target <= *points.last().unwrap() {
+            continue;
+        }
+        let len = read_full_at(file, &mut window, target)?;
+        let window = &window[..len];
+
+        let split = match memchr::memchr(b'\n', window) {
+            Some(newline) => Some(newline + 1),
+            None if word_mode == WordMode::Whitespace
+                && !needs.max_line_length
+                && !needs.graphemes =>
+            {
+                window
+                    .iter()
+                    .position(|&byte| byte & 0b1100_0000 != 0b1000_0000)
+            }
+            None => None,
+        };
+        if let Some(split) = split {
+            let point = target + split as u64;
+            if point < size {
+                points.push(point);
+            }
+        }
+    }
+    points.push(size);
+    Ok(points)
+}
+
+fn count_stretch(
+    file: &File,
+    start: u64,
+    end: u64,
+    word_mode: WordMode,
+    needs: Needs,
+    snapshot: &Mutex<Count>,
+) -> io::Result<(Count, counter::Edges)> {
+    let mut counter = Counter::with_needs(word_mode, needs);
+    let mut buf = vec![0; BUF_SIZE];
+    let mut offset = start;
+    while offset < end {
+        let len = buf.len().min((end - offset) as usize);
+        let read = read_full_at(file, &mut buf[..len], offset)?;
+        if read == 0 {
+            // the file shrank while we were counting it
+            break;
+        }
+        counter.update(&buf[..read]);
+        offset += read as u64;
+        *snapshot.lock().unwrap() = counter.snapshot();
+    }
+    Ok(counter.finish_with_edges())
+}
+
+// like FileExt::read_exact_at, but stopping short at the end of the file
+fn read_full_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match file.read_at(&mut buf[filled..], offset + filled as u64) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
@@ -0,0 +1,47 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(any(
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "netbsd",
+    target_os = "openbsd",
+    target_os = "dragonfly"
+))]
+const SIGNALS: &[libc::c_int] = &[libc::SIGUSR1, libc::SIGINFO];
+#[cfg(not(any(
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "netbsd",
+    target_os = "openbsd",
+    target_os = "dragonfly"
+)))]
+const SIGNALS: &[libc::c_int] = &[libc::SIGUSR1];
+
+extern "C" fn request(_signal: libc::c_int) {
+    REQUESTED.store(true, Ordering::Relaxed);
+}
+
+/// Asks for a progress report on SIGUSR1, and on SIGINFO where there is one.
+///
+/// The handler only sets a flag for the counting loops to pick up. It is
+/// installed without SA_RESTART, so a read waiting on a pipe is interrupted
+/// and the report goes out at once.
+pub fn install() {
+    for &signal in SIGNALS {
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = request as extern "C" fn(libc::c_int) as libc::sighandler_t;
+            libc::sigemptyset(&mut action.sa_mask);
+            libc::sigaction(signal, &action, std::ptr::null_mut());
+        }
+    }
+}
+
+/// Whether a report was asked for since the last call.
+pub fn requested() -> bool {
+    REQUESTED.swap(false, Ordering::Relaxed)
+}
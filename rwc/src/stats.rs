@@ -0,0 +1,134 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// How many of the longest lines of an input to give the line numbers of.
+const MAX_LOCATIONS: usize = 10;
+
+/// What line lengths are measured in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LengthUnit {
+    Bytes,
+    // display width, as -L measures it
+    Columns,
+}
+
+impl FromStr for LengthUnit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bytes" => Ok(LengthUnit::Bytes),
+            "columns" => Ok(LengthUnit::Columns),
+            _ => Err(format!("unknown length unit '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for LengthUnit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LengthUnit::Bytes => f.write_str("bytes"),
+            LengthUnit::Columns => f.write_str("columns"),
+        }
+    }
+}
+
+/// The lengths of the lines of an input, not counting their line breaks.
+///
+/// Lengths are kept as how many lines have each one, which takes little
+/// room however many lines there are and still gives exact percentiles.
+#[derive(Debug, Clone, Default)]
+pub struct LineStats {
+    lengths: BTreeMap<u64, u64>,
+    // lines with nothing on them but the line break
+    empty: u64,
+    // the first few line numbers of the longest lines, only known of a
+    // single input
+    longest_at: Vec<u64>,
+}
+
+impl LineStats {
+    /// Adds line number `line`, `length` long.
+    pub fn add(&mut self, line: u64, length: u64, empty: bool) {
+        let longest = self.max().unwrap_or(0);
+        if length > longest {
+            self.longest_at.clear();
+        }
+        if length >= longest && self.longest_at.len() < MAX_LOCATIONS {
+            self.longest_at.push(line);
+        }
+        *self.lengths.entry(length).or_default() += 1;
+        self.empty += empty as u64;
+    }
+
+    /// Adds the lines of another input, losing track of where the longest
+    /// lines were, since line numbers can't say which input they are in.
+    pub fn merge(&mut self, other: &LineStats) {
+        for (&length, &lines) in &other.lengths {
+            *self.lengths.entry(length).or_default() += lines;
+        }
+        self.empty += other.empty;
+        self.longest_at.clear();
+    }
+
+    pub fn lines(&self) -> u64 {
+        self.lengths.values().sum()
+    }
+
+    pub fn empty(&self) -> u64 {
+        self.empty
+    }
+
+    pub fn min(&self) -> Option<u64> {
+        self.lengths.keys().next().copied()
+    }
+
+    pub fn max(&self) -> Option<u64> {
+        self.lengths.keys().next_back().copied()
+    }
+
+    pub fn mean(&self) -> Option<f64> {
+        let total: u64 = self
+            .lengths
+            .iter()
+            .map(|(length, lines)| length * lines)
+            .sum();
+        let lines = self.lines();
+        (lines > 0).then(|| total as f64 / lines as f64)
+    }
+
+    /// The nearest rank percentile: the shortest length that at least
+    /// `percent` of lines are no longer than.
+    pub fn percentile(&self, percent: u64) -> Option<u64> {
+        let rank = (self.lines() * percent).div_ceil(100).max(1);
+        let mut seen = 0;
+        self.lengths.iter().find_map(|(&length, &lines)| {
+            seen += lines;
+            (seen >= rank).then_some(length)
+        })
+    }
+
+    /// How many lines are as long as the longest.
+    pub fn longest(&self) -> u64 {
+        self.lengths.values().next_back().copied().unwrap_or(0)
+    }
+
+    pub fn longest_at(&self) -> &[u64] {
+        &self.longest_at
+    }
+
+    /// How many lines fall into each bucket `width` long, from the first
+    /// length of each bucket. Empty buckets are left out.
+    pub fn histogram(&self, width: u64) -> Vec<(u64, u64)> {
+        let mut buckets: Vec<(u64, u64)> = Vec::new();
+        for (&length, &lines) in &self.lengths {
+            let start = length - length % width;
+            match buckets.last_mut() {
+                Some((last, bucket_lines)) if *last == start => *bucket_lines += lines,
+                _ => buckets.push((start, lines)),
+            }
+        }
+        buckets
+    }
+}
@@ -0,0 +1,229 @@
+use crate::counter::cut_off_len;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Words are counted by at most their first this many bytes, so however
+/// long a word runs, --top holds on to little of it.
+const MAX_WORD_LEN: usize = 256;
+
+/// How --top splits lines into words.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Tokenizer {
+    // runs of anything but whitespace
+    Whitespace,
+    // UAX #29 word segments containing a letter or digit
+    Unicode,
+    // letters, digits and underscores, not starting with a digit
+    Identifier,
+}
+
+impl FromStr for Tokenizer {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "whitespace" => Ok(Tokenizer::Whitespace),
+            "unicode" => Ok(Tokenizer::Unicode),
+            "identifier" => Ok(Tokenizer::Identifier),
+            _ => Err(format!("unknown tokenizer '{}'", s)),
+        }
+    }
+}
+
+/// The most frequent words of all inputs together, for --top.
+#[derive(Debug)]
+pub struct TopWords {
+    tokenizer: Tokenizer,
+    ignore_case: bool,
+    stopwords: HashSet<String>,
+    frequencies: Mutex<Frequencies>,
+}
+
+impl TopWords {
+    /// Reads the stopwords, if any, from a file of words split as
+    /// `tokenizer` splits them.
+    pub fn new(
+        tokenizer: Tokenizer,
+        ignore_case: bool,
+        stopwords: Option<&Path>,
+        capacity: usize,
+    ) -> io::Result<Self> {
+        let mut top_words = TopWords {
+            tokenizer,
+            ignore_case,
+            stopwords: HashSet::new(),
+            frequencies: Mutex::new(Frequencies::new(capacity)),
+        };
+        if let Some(path) = stopwords {
+            let text = fs::read(path)?;
+            let mut stopwords = HashSet::new();
+            top_words.for_each_word(&String::from_utf8_lossy(&text), |word| {
+                stopwords.insert(word.to_string());
+            });
+            top_words.stopwords = stopwords;
+        }
+        Ok(top_words)
+    }
+
+    // counts the words of text that no word goes on past the end of
+    fn add_text(&self, text: &str) {
+        let mut frequencies = self.frequencies.lock().unwrap();
+        self.for_each_word(text, |word| {
+            if !self.stopwords.contains(word) {
+                frequencies.add(word);
+            }
+        });
+    }
+
+    // where the end of text that more text may change the words of starts
+    fn unfinished_start(&self, text: &str) -> usize {
+        let last_split = |is_split: fn(char) -> bool| {
+            text.char_indices()
+                .rev()
+                .find(|&(_, char)| is_split(char))
+                .map_or(0, |(index, char)| index + char.len_utf8())
+        };
+        match self.tokenizer {
+            Tokenizer::Whitespace => last_split(char::is_whitespace),
+            Tokenizer::Identifier => last_split(|char| !(char.is_alphanumeric() || char == '_')),
+            Tokenizer::Unicode => {
+                // the boundary before the last segment can still go away,
+                // as "can" and "'" join when "t" follows, so the segment
+                // before it waits too, unless it is longer than any word
+                let mut segments = text.split_word_bound_indices().rev();
+                match (segments.next(), segments.next()) {
+                    (Some(_), Some((start, segment))) if segment.len() <= MAX_WORD_LEN => start,
+                    (Some((start, _)), _) => start,
+                    (None, _) => 0,
+                }
+            }
+        }
+    }
+
+    /// The `n` most frequent words, most frequent first, and how much their
+    /// counts may be too low by if there were too many words to keep count
+    /// of them all.
+    pub fn top(&self, n: usize) -> (Vec<(String, u64)>, u64) {
+        let frequencies = self.frequencies.lock().unwrap();
+        let mut words: Vec<_> = frequencies
+            .counts
+            .iter()
+            .map(|(word, &count)| (word.clone(), count))
+            .collect();
+        words.sort_unstable_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then(a.cmp(b)));
+        words.truncate(n);
+        (words, frequencies.max_error)
+    }
+
+    fn for_each_word(&self, text: &str, mut add: impl FnMut(&str)) {
+        let add_word = |word: &str| {
+            let word = &word[..word.floor_char_boundary(MAX_WORD_LEN)];
+            if self.ignore_case {
+                add(&word.to_lowercase());
+            } else {
+                add(word);
+            }
+        };
+        match self.tokenizer {
+            Tokenizer::Whitespace => text.split_whitespace().for_each(add_word),
+            Tokenizer::Unicode => text.unicode_words().for_each(add_word),
+            Tokenizer::Identifier => text
+                .split(|char: char| !(char.is_alphanumeric() || char == '_'))
+                .filter(|word| {
+                    !word.is_empty() && !word.starts_with(|char: char| char.is_numeric())
+                })
+                .for_each(add_word),
+        }
+    }
+}
+
+/// Splits one input into words for --top as it streams by, holding on to
+/// no more of it than the start of a word the next chunk may go on with.
+pub struct WordSplitter {
+    top_words: Arc<TopWords>,
+    // the end of the input so far that more input may change the words of
+    pending: Vec<u8>,
+}
+
+impl WordSplitter {
+    pub fn new(top_words: Arc<TopWords>) -> Self {
+        WordSplitter {
+            top_words,
+            pending: Vec::new(),
+        }
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        self.pending.extend_from_slice(bytes);
+        let end = self.pending.len() - cut_off_len(&self.pending);
+        let text = String::from_utf8_lossy(&self.pending[..end]);
+        let start = self.top_words.unfinished_start(&text);
+        self.top_words.add_text(&text[..start]);
+
+        // past its first MAX_WORD_LEN bytes, no word is counted any longer,
+        // and the unfinished end holds at most two words
+        let unfinished = &text[start..];
+        let unfinished = &unfinished[..unfinished.floor_char_boundary(2 * MAX_WORD_LEN)];
+        let mut pending = Vec::with_capacity(unfinished.len() + self.pending.len() - end);
+        pending.extend_from_slice(unfinished.as_bytes());
+        pending.extend_from_slice(&self.pending[end..]);
+        self.pending = pending;
+    }
+
+    pub fn finish(&mut self) {
+        self.top_words
+            .add_text(&String::from_utf8_lossy(&self.pending));
+        self.pending.clear();
+    }
+}
+
+/// How often each word comes up, keeping count of at most `capacity` words.
+///
+/// Past that, room is made as in the Misra-Gries frequent items algorithm:
+/// every count goes down by the same amount, enough to drop at least half
+/// of the words, so any word that makes up more than 2 / `capacity` of the
+/// input is still counted, if short by at most `max_error`.
+#[derive(Debug)]
+struct Frequencies {
+    counts: HashMap<String, u64>,
+    capacity: usize,
+    max_error: u64,
+}
+
+impl Frequencies {
+    fn new(capacity: usize) -> Self {
+        Frequencies {
+            counts: HashMap::new(),
+            capacity: capacity.max(2),
+            max_error: 0,
+        }
+    }
+
+    fn add(&mut self, word: &str) {
+        match self.counts.get_mut(word) {
+            Some(count) => *count += 1,
+            None => {
+                self.counts.insert(word.to_string(), 1);
+                if self.counts.len() > self.capacity {
+                    self.make_room();
+                }
+            }
+        }
+    }
+
+    fn make_room(&mut self) {
+        let mut counts: Vec<u64> = self.counts.values().copied().collect();
+        let drop = counts.len() - self.capacity / 2;
+        let (_, &mut decrease, _) = counts.select_nth_unstable(drop - 1);
+        self.counts.retain(|_, count| {
+            *count -= decrease.min(*count);
+            *count > 0
+        });
+        self.max_error += decrease;
+    }
+}
@@ -1,6 +1,11 @@
+use assert_cmd::assert::OutputAssertExt;
+use assert_cmd::cargo::cargo_bin;
 use assert_cmd::Command;
+use assert_fs::prelude::*;
 use predicates::prelude::*;
 use std::error::Error;
+use std::io::Write;
+use std::process::{self, Stdio};
 
 type TestResult = Result<(), Box<dyn Error>>;
 
@@ -105,7 +110,7 @@ fn skips_bad_files() -> TestResult {
         .arg(LOCKED_FILE)
         .arg(WINDOWS_UTF8_FILE)
         .assert()
-        .success()
+        .code(1)
         .stderr(predicate::str::contains(format!(
             "rwc: {}",
             NONEXISTENT_FILE
@@ -144,7 +149,7 @@ fn l_flag_alone() -> TestResult {
 fn w_flag_alone() -> TestResult {
     let mut wc = Command::new("wc");
     let expected_result = String::from_utf8(
-        wc.arg("w")
+        wc.arg("-w")
             .arg(ASCII_FILE_1)
             .arg(ASCII_FILE_2)
             .arg(WINDOWS_UTF8_FILE)
@@ -153,7 +158,7 @@ fn w_flag_alone() -> TestResult {
     )?;
 
     let mut rwc = Command::cargo_bin("rwc")?;
-    rwc.arg("w")
+    rwc.arg("-w")
         .arg(ASCII_FILE_1)
         .arg(ASCII_FILE_2)
         .arg(WINDOWS_UTF8_FILE)
@@ -213,7 +218,7 @@ fn m_flag_alone() -> TestResult {
 }
 
 #[test]
-fn c_overrides_m() -> TestResult {
+fn mc_flags_print_chars_and_bytes() -> TestResult {
     let mut wc = Command::new("wc");
     let expected_result = String::from_utf8(
         wc.arg("-mc")
@@ -237,7 +242,7 @@ fn c_overrides_m() -> TestResult {
 }
 
 #[test]
-fn m_overrides_c() -> TestResult {
+fn cm_flags_print_chars_and_bytes() -> TestResult {
     let mut wc = Command::new("wc");
     let expected_result = String::from_utf8(
         wc.arg("-cm")
@@ -292,3 +297,1051 @@ fn unknown_flag_shows_usage() -> TestResult {
 
     Ok(())
 }
+
+#[test]
+fn upper_l_flag_reports_longest_line_width() -> TestResult {
+    // tabs stop every 8 columns and \r starts the line over
+    let input = "short\nab\tc\tlonger tail\nxxxxxxxxxxxxxxxxxxxxxxxx\rmid\n";
+
+    let mut rwc = Command::cargo_bin("rwc")?;
+    rwc.arg("-L")
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout("27\n");
+
+    let mut rwc = Command::cargo_bin("rwc")?;
+    rwc.arg("-lL")
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout("      3      27\n");
+
+    Ok(())
+}
+
+#[test]
+fn upper_l_flag_counts_wide_chars_twice() -> TestResult {
+    for (flag, expected) in [("-L", "11\n"), ("-mL", "     14      11\n")] {
+        let mut rwc = Command::cargo_bin("rwc")?;
+        rwc.arg(flag)
+            .write_stdin("日本語 text\ncafé\n")
+            .assert()
+            .success()
+            .stdout(expected);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn upper_l_flag_total_is_longest_of_all() -> TestResult {
+    let dir = assert_fs::TempDir::new()?;
+    let short = dir.child("short.txt");
+    short.write_str("abc\n")?;
+    let long = dir.child("long.txt");
+    long.write_str("abcdefghij\nabc\n")?;
+
+    let mut rwc = Command::cargo_bin("rwc")?;
+    rwc.arg("-L")
+        .arg(short.path())
+        .arg(long.path())
+        .assert()
+        .success()
+        .stdout(predicate::str::ends_with("\n10 total\n"));
+
+    Ok(())
+}
+
+fn fields(output: &[u8]) -> Vec<String> {
+    String::from_utf8_lossy(output)
+        .split_whitespace()
+        .map(String::from)
+        .collect()
+}
+
+#[test]
+fn counts_words_like_gnu_wc() -> TestResult {
+    let ascii = "  indented  words\there\n\n\tdouble  spaced\x0bvertical\x01ctl end";
+    let unicode = "caf\u{e9}\u{a0}au\u{3000}lait \u{2028} na\u{ef}ve\n";
+
+    for (flag, input) in [
+        ("-w", ascii),
+        ("-lwc", ascii),
+        ("-mw", ascii),
+        ("-mw", unicode),
+        ("-lwm", unicode),
+    ] {
+        let mut wc = process::Command::new("wc")
+            .env("LC_ALL", "C.UTF-8")
+            .arg(flag)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        wc.stdin.take().unwrap().write_all(input.as_bytes())?;
+        let expected = fields(&wc.wait_with_output()?.stdout);
+
+        let rwc_output = Command::cargo_bin("rwc")?
+            .arg(flag)
+            .write_stdin(input)
+            .output()?;
+        assert_eq!(fields(&rwc_output.stdout), expected, "rwc {}", flag);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn unicode_word_mode_splits_on_word_boundaries() -> TestResult {
+    for (flag, expected) in [("-w", "9\n"), ("-mw", "      9 ")] {
+        let mut rwc = Command::cargo_bin("rwc")?;
+        rwc.arg(flag)
+            .arg("--words=unicode")
+            .write_stdin("日本語 text, (with) punctuation...\nit's 3.5 km!\n")
+            .assert()
+            .success()
+            .stdout(predicate::str::starts_with(expected));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn unicode_word_mode_counts_input_without_line_breaks() -> TestResult {
+    // words run across chunk edges, and two are too long to hold whole
+    let input = format!(
+        "{}{} x{}yz can't",
+        "can't stop, 3.5 x.y ".repeat(10_000),
+        "a".repeat(100_000),
+        "\u{301}".repeat(100_000)
+    );
+    let mut rwc = Command::cargo_bin("rwc")?;
+    rwc.args(["-w", "--words=unicode"])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout("40003\n");
+
+    Ok(())
+}
+
+#[test]
+fn unknown_word_mode_shows_usage() -> TestResult {
+    let mut rwc = Command::cargo_bin("rwc")?;
+    rwc.arg("--words=letters")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("USAGE"));
+
+    Ok(())
+}
+
+#[test]
+fn prints_columns_in_gnu_order() -> TestResult {
+    let input = "héllo wörld\n\tsecond line\n";
+    let expected = "      2       4      25      27      19\n";
+
+    for flags in [
+        ["-L", "-c", "-m", "-w", "-l"],
+        ["-l", "-w", "-m", "-c", "-L"],
+    ] {
+        let mut rwc = Command::cargo_bin("rwc")?;
+        rwc.args(flags)
+            .write_stdin(input)
+            .assert()
+            .success()
+            .stdout(expected);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn cm_flags_match_gnu_wc() -> TestResult {
+    let input = "naïve café\n日本語\n";
+
+    let mut wc = process::Command::new("wc")
+        .env("LC_ALL", "C.UTF-8")
+        .arg("-cm")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    wc.stdin.take().unwrap().write_all(input.as_bytes())?;
+    let expected = fields(&wc.wait_with_output()?.stdout);
+
+    let rwc_output = Command::cargo_bin("rwc")?
+        .arg("-cm")
+        .write_stdin(input)
+        .output()?;
+    assert_eq!(fields(&rwc_output.stdout), expected);
+
+    Ok(())
+}
+
+#[test]
+fn reports_read_errors_and_keeps_going() -> TestResult {
+    let dir = assert_fs::TempDir::new()?;
+    let file = dir.child("file.txt");
+    file.write_str("one two\n")?;
+
+    let mut rwc = Command::cargo_bin("rwc")?;
+    rwc.arg(dir.path())
+        .arg(file.path())
+        .assert()
+        .code(1)
+        .stderr(predicate::str::starts_with(format!(
+            "rwc: {}: Is a directory",
+            dir.path().display()
+        )))
+        .stdout(predicate::str::contains(format!(
+            "      1       2       8 {}\n",
+            file.path().display()
+        )))
+        .stdout(predicate::str::ends_with("      1       2       8 total\n"));
+
+    Ok(())
+}
+
+#[test]
+fn reports_stdin_read_errors() -> TestResult {
+    let dir = assert_fs::TempDir::new()?;
+
+    let output = process::Command::new(cargo_bin("rwc"))
+        .stdin(std::fs::File::open(dir.path())?)
+        .output()?;
+    output.assert().code(1).stderr(predicate::str::starts_with(
+        "rwc: standard input: Is a directory",
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn m_flag_skips_invalid_utf8_like_gnu_wc() -> TestResult {
+    let input: &[u8] = b"caf\xe9 ol\xc3\xa9\n\xff\xfe\n\xe6\x97\xa5\xe6\x97 end\n";
+
+    for flag in ["-m", "-lwmcL"] {
+        let mut wc = process::Command::new("wc")
+            .env("LC_ALL", "C.UTF-8")
+            .arg(flag)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        wc.stdin.take().unwrap().write_all(input)?;
+        let expected = fields(&wc.wait_with_output()?.stdout);
+
+        let rwc_output = Command::cargo_bin("rwc")?
+            .arg(flag)
+            .write_stdin(input)
+            .output()?;
+        assert!(rwc_output.status.success());
+        assert_eq!(fields(&rwc_output.stdout), expected, "rwc {}", flag);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn threads_count_large_files_like_one_thread() -> TestResult {
+    // lines longer than rwc's split window, so some splits land mid-line
+    // and mid-word, between multibyte characters
+    let long_line = "wörd 日本語\tsplít\u{a0}here \x01ctl ".repeat(4_000);
+    let short_lines = "one two\n\nthree\r\n".repeat(20_000);
+    let mut text = String::new();
+    while text.len() < 24 * 1024 * 1024 {
+        text.push_str(&long_line);
+        text.push('\n');
+        text.push_str(&short_lines);
+    }
+    let file = assert_fs::NamedTempFile::new("large.txt")?;
+    file.write_str(&text)?;
+
+    for flags in [&[][..], &["-lwmcL"], &["-mw"], &["-lw", "--words=unicode"]] {
+        let sequential = Command::cargo_bin("rwc")?
+            .args(flags)
+            .arg("--threads=1")
+            .arg(file.path())
+            .output()?;
+        let threaded = Command::cargo_bin("rwc")?
+            .args(flags)
+            .arg("--threads=4")
+            .arg(file.path())
+            .output()?;
+        assert!(threaded.status.success());
+        assert_eq!(threaded.stdout, sequential.stdout, "rwc {:?}", flags);
+    }
+
+    // one column at a time, as counts this big run into each other
+    for flag in ["-l", "-w", "-m", "-L"] {
+        let threaded = Command::cargo_bin("rwc")?
+            .args([flag, "--threads=4"])
+            .arg(file.path())
+            .output()?;
+        let wc = process::Command::new("wc")
+            .env("LC_ALL", "C.UTF-8")
+            .arg(flag)
+            .arg(file.path())
+            .output()?;
+        assert_eq!(fields(&threaded.stdout), fields(&wc.stdout), "wc {}", flag);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn files0_from_reads_nul_separated_names() -> TestResult {
+    let dir = assert_fs::TempDir::new()?;
+    let (one, two) = (dir.child("one.txt"), dir.child("two words.txt"));
+    one.write_str("one two\n")?;
+    two.write_str("three\n")?;
+    let list = dir.child("list");
+    list.write_binary(format!("{}\0{}\0", one.path().display(), two.path().display()).as_bytes())?;
+
+    let (one, two) = (one.path().display(), two.path().display());
+
+    // as in GNU wc, columns are only sized when the list can be read ahead
+    let mut rwc = Command::cargo_bin("rwc")?;
+    rwc.arg("--files0-from")
+        .arg(list.path())
+        .assert()
+        .success()
+        .stdout(format!(
+            " 1  2  8 {}\n 1  1  6 {}\n 2  3 14 total\n",
+            one, two
+        ));
+
+    let mut rwc = Command::cargo_bin("rwc")?;
+    rwc.arg("--files0-from=-")
+        .write_stdin(std::fs::read(list.path())?)
+        .assert()
+        .success()
+        .stdout(format!("1 2 8 {}\n1 1 6 {}\n2 3 14 total\n", one, two));
+
+    Ok(())
+}
+
+#[test]
+fn files0_from_reports_bad_names_and_keeps_going() -> TestResult {
+    let dir = assert_fs::TempDir::new()?;
+    let file = dir.child("file.txt");
+    file.write_str("one two\n")?;
+
+    let mut rwc = Command::cargo_bin("rwc")?;
+    rwc.arg("--files0-from=-")
+        .write_stdin(format!("\0-\0{}", file.path().display()))
+        .assert()
+        .code(1)
+        .stderr(
+            "rwc: -:1: invalid zero-length file name\n\
+             rwc: when reading file names from stdin, no file name of '-' allowed\n",
+        )
+        .stdout(format!("1 2 8 {}\n1 2 8 total\n", file.path().display()));
+
+    Ok(())
+}
+
+#[test]
+fn files0_from_fails_on_missing_list_or_file_operands() -> TestResult {
+    let mut rwc = Command::cargo_bin("rwc")?;
+    rwc.arg(format!("--files0-from={}", NONEXISTENT_FILE))
+        .assert()
+        .code(1)
+        .stderr(predicate::str::starts_with(format!(
+            "rwc: cannot open '{}' for reading",
+            NONEXISTENT_FILE
+        )));
+
+    let mut rwc = Command::cargo_bin("rwc")?;
+    rwc.args(["--files0-from=-", "file.txt"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("cannot be used with"));
+
+    Ok(())
+}
+
+#[test]
+fn total_flag_controls_the_total_line() -> TestResult {
+    let dir = assert_fs::TempDir::new()?;
+    let file = dir.child("file.txt");
+    file.write_str("one two\n")?;
+    let name = file.path().display();
+
+    for (when, args, expected) in [
+        ("auto", 1, format!("1 2 8 {}\n", name)),
+        (
+            "auto",
+            2,
+            format!(" 1  2  8 {0}\n 1  2  8 {0}\n 2  4 16 total\n", name),
+        ),
+        ("always", 1, format!("1 2 8 {}\n1 2 8 total\n", name)),
+        ("only", 2, String::from(" 2  4 16\n")),
+        ("never", 2, format!(" 1  2  8 {0}\n 1  2  8 {0}\n", name)),
+    ] {
+        let mut rwc = Command::cargo_bin("rwc")?;
+        rwc.arg(format!("--total={}", when))
+            .args(vec![file.path(); args])
+            .assert()
+            .success()
+            .stdout(expected);
+    }
+
+    let mut rwc = Command::cargo_bin("rwc")?;
+    rwc.arg("--total=always")
+        .write_stdin("one two\n")
+        .assert()
+        .success()
+        .stdout("      1       2       8\n      1       2       8 total\n");
+
+    let mut rwc = Command::cargo_bin("rwc")?;
+    rwc.arg("--total=sometimes")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("USAGE"));
+
+    Ok(())
+}
+
+#[test]
+fn format_flag_prints_json_records() -> TestResult {
+    let dir = assert_fs::TempDir::new()?;
+    let file = dir.child("two words.txt");
+    file.write_str("one two\n")?;
+    let name = file.path().display();
+
+    let mut rwc = Command::cargo_bin("rwc")?;
+    rwc.args(["--format=json", "-lm"])
+        .args([file.path(), file.path()])
+        .assert()
+        .success()
+        .stdout(format!(
+            "{{\"files\":[\n\
+             {{\"file\":\"{0}\",\"lines\":1,\"chars\":8}},\n\
+             {{\"file\":\"{0}\",\"lines\":1,\"chars\":8}}\n\
+             ],\"total\":{{\"lines\":2,\"chars\":16}}}}\n",
+            name
+        ));
+
+    let mut rwc = Command::cargo_bin("rwc")?;
+    rwc.arg("--format=json")
+        .write_stdin("one two\n")
+        .assert()
+        .success()
+        .stdout("{\"files\":[\n{\"file\":null,\"lines\":1,\"words\":2,\"bytes\":8}\n]}\n");
+
+    Ok(())
+}
+
+#[test]
+fn format_flag_prints_csv_and_tsv_rows() -> TestResult {
+    let dir = assert_fs::TempDir::new()?;
+    let file = dir.child("a \"b\",\tc.txt");
+    file.write_str("one two\n")?;
+    let dir_name = dir.path().display();
+
+    for (format, expected) in [
+        (
+            "csv",
+            format!(
+                "file,lines,words,bytes\n\"{}/a \"\"b\"\",\tc.txt\",1,2,8\ntotal,1,2,8\n",
+                dir_name
+            ),
+        ),
+        (
+            "tsv",
+            format!(
+                "file\tlines\twords\tbytes\n{}/a \"b\",\\tc.txt\t1\t2\t8\ntotal\t1\t2\t8\n",
+                dir_name
+            ),
+        ),
+    ] {
+        let mut rwc = Command::cargo_bin("rwc")?;
+        rwc.arg(format!("--format={}", format))
+            .arg("--total=always")
+            .arg(file.path())
+            .assert()
+            .success()
+            .stdout(expected);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn format_flag_escapes_non_utf8_paths() -> TestResult {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let dir = assert_fs::TempDir::new()?;
+    let file = dir.child(OsStr::from_bytes(b"caf\xe9\n.txt"));
+    file.write_str("one\n")?;
+
+    let mut rwc = Command::cargo_bin("rwc")?;
+    rwc.args(["--format=json", "-l"])
+        .arg(file.path())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(format!(
+            "{{\"file\":\"{}/caf\\udce9\\n.txt\",\"lines\":1}}",
+            dir.path().display()
+        )));
+
+    let mut rwc = Command::cargo_bin("rwc")?;
+    rwc.args(["--format=csv", "-l"])
+        .arg(file.path())
+        .assert()
+        .success()
+        .stdout(format!(
+            "file,lines\n\"{}/caf\\xe9\n.txt\",1\n",
+            dir.path().display()
+        ));
+
+    let mut rwc = Command::cargo_bin("rwc")?;
+    rwc.args(["--format=tsv", "-l"])
+        .arg(file.path())
+        .assert()
+        .success()
+        .stdout(format!(
+            "file\tlines\n{}/caf\\xe9\\n.txt\t1\n",
+            dir.path().display()
+        ));
+
+    // a name spelling out \xff apart from one with that byte in it
+    dir.child(OsStr::from_bytes(b"a\xff")).write_str("one\n")?;
+    dir.child("a\\xff").write_str("one\n")?;
+    for (format, expected) in [
+        ("csv", "file,lines\na\\xff,1\na\\\\xff,1\ntotal,2\n"),
+        ("tsv", "file\tlines\na\\xff\t1\na\\\\xff\t1\ntotal\t2\n"),
+    ] {
+        let mut rwc = Command::cargo_bin("rwc")?;
+        rwc.current_dir(dir.path())
+            .arg(format!("--format={}", format))
+            .arg("-l")
+            .arg(OsStr::from_bytes(b"a\xff"))
+            .arg("a\\xff")
+            .assert()
+            .success()
+            .stdout(expected);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn unknown_format_shows_usage() -> TestResult {
+    let mut rwc = Command::cargo_bin("rwc")?;
+    rwc.arg("--format=xml")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("USAGE"));
+
+    Ok(())
+}
+
+#[test]
+fn sizes_columns_like_gnu_wc() -> TestResult {
+    let dir = assert_fs::TempDir::new()?;
+    let small = dir.child("small.txt");
+    small.write_str("one two\n")?;
+    let large = dir.child("large.txt");
+    large.write_str(&"word\n".repeat(30_000))?;
+
+    for args in [
+        vec!["-l"],
+        vec![],
+        vec!["-l", "small.txt"],
+        vec!["small.txt"],
+        vec!["-lwmcL", "small.txt", "large.txt"],
+        vec!["-", "small.txt"],
+        vec!["-c", "large.txt", "missing.txt"],
+    ] {
+        let wc = process::Command::new("wc")
+            .env("LC_ALL", "C.UTF-8")
+            .current_dir(dir.path())
+            .args(&args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?
+            .wait_with_output()?;
+
+        let mut rwc = Command::cargo_bin("rwc")?;
+        rwc.current_dir(dir.path())
+            .args(&args)
+            .write_stdin("")
+            .assert()
+            .stdout(String::from_utf8(wc.stdout)?);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn group_digits_flag_separates_thousands() -> TestResult {
+    let file = assert_fs::NamedTempFile::new("large.txt")?;
+    file.write_str(&"word\n".repeat(300_000))?;
+
+    let mut rwc = Command::cargo_bin("rwc")?;
+    rwc.args(["--group-digits", "-lc"])
+        .arg(file.path())
+        .assert()
+        .success()
+        .stdout(format!("  300,000 1,500,000 {}\n", file.path().display()));
+
+    Ok(())
+}
+
+#[test]
+fn c_flag_reads_files_whose_size_is_unreliable() -> TestResult {
+    // /proc files report a size of 0, so taking it from stat would be wrong
+    let size = std::fs::read("/proc/version")?.len();
+
+    let mut rwc = Command::cargo_bin("rwc")?;
+    rwc.args(["-c", "/proc/version"])
+        .assert()
+        .success()
+        .stdout(format!("{} /proc/version\n", size));
+
+    Ok(())
+}
+
+#[test]
+fn c_flag_counts_stdin_from_its_position() -> TestResult {
+    let file = assert_fs::NamedTempFile::new("file.txt")?;
+    file.write_str("one two three\n")?;
+    let mut stdin = std::fs::File::open(file.path())?;
+    std::io::Seek::seek(&mut stdin, std::io::SeekFrom::Start(4))?;
+
+    let output = process::Command::new(cargo_bin("rwc"))
+        .arg("-c")
+        .stdin(stdin)
+        .output()?;
+    output.assert().success().stdout("10\n");
+
+    Ok(())
+}
+
+#[test]
+fn code_flag_sorts_lines_of_rust() -> TestResult {
+    let file = assert_fs::NamedTempFile::new("main.rs")?;
+    file.write_str(
+        "// comment\n\
+         /* block\n\
+         \x20 /* nested */\n\
+         \n\
+         \x20 still a comment */\n\
+         fn main() { // trailing\n\
+         \n\
+         \x20   let s = \"a string\n\
+         // in a string\n\
+         \";\n\
+         \x20   let r = r#\"raw \"quoted\" /*\"#;\n\
+         \x20   let c = '\"'; let l: &'static str = \"x\";\n\
+         \x20   /* only a comment */\n\
+         }\n",
+    )?;
+
+    let mut rwc = Command::cargo_bin("rwc")?;
+    rwc.arg("--code")
+        .arg(file.path())
+        .assert()
+        .success()
+        .stdout(format!("  7   5   2 {} (Rust)\n", file.path().display()));
+
+    Ok(())
+}
+
+#[test]
+fn code_flag_handles_python_shell_c_and_toml() -> TestResult {
+    let dir = assert_fs::TempDir::new()?;
+    for (name, source, expected) in [
+        (
+            "tool",
+            "#!/usr/bin/env python3\n# comment\nx = \"# not a comment\"\ndoc = \"\"\"\n# in a docstring\n\"\"\"\n\ny = 'it\\'s'  # trailing\n",
+            "5 2 1 tool (Python)\n",
+        ),
+        (
+            "build",
+            "#!/bin/sh\n# comment\necho $# '# not\n# a comment'\necho ${#x} # trailing\n",
+            "3 2 0 build (Shell)\n",
+        ),
+        (
+            "main.c",
+            "/* header\n * comment */\n#include <stdio.h>\n\nint main() { puts(\"/* no */\"); } // end\n",
+            "2 2 1 main.c (C)\n",
+        ),
+        (
+            "Cargo.toml",
+            "# comment\n[package]\ndescription = \"\"\"\n# not a comment\n\"\"\"\n",
+            "4 1 0 Cargo.toml (TOML)\n",
+        ),
+    ] {
+        dir.child(name).write_str(source)?;
+
+        let rwc_output = Command::cargo_bin("rwc")?
+            .current_dir(dir.path())
+            .args(["--code", name])
+            .output()?;
+        assert!(rwc_output.status.success());
+        assert_eq!(fields(&rwc_output.stdout), fields(expected.as_bytes()));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn code_flag_totals_each_language() -> TestResult {
+    let dir = assert_fs::TempDir::new()?;
+    dir.child("a.rs").write_str("// a\nfn a() {}\n")?;
+    dir.child("b.rs").write_str("fn b() {}\n\n")?;
+    dir.child("c.py").write_str("# c\n")?;
+    dir.child("notes.txt").write_str("notes\n")?;
+    let files = ["notes.txt", "c.py", "a.rs", "b.rs"];
+
+    let mut rwc = Command::cargo_bin("rwc")?;
+    rwc.current_dir(dir.path())
+        .arg("--code")
+        .args(files)
+        .assert()
+        .success()
+        .stdout(
+            " 1  0  0 notes.txt (unknown)\n\
+             \x200  1  0 c.py (Python)\n\
+             \x201  1  0 a.rs (Rust)\n\
+             \x201  0  1 b.rs (Rust)\n\
+             \x200  1  0 total (Python)\n\
+             \x202  1  1 total (Rust)\n\
+             \x201  0  0 total (unknown)\n\
+             \x203  2  1 total\n",
+        );
+
+    let mut rwc = Command::cargo_bin("rwc")?;
+    rwc.current_dir(dir.path())
+        .args(["--code", "--format=json", "--total=only"])
+        .args(files)
+        .assert()
+        .success()
+        .stdout(
+            "{\"files\":[],\"languages\":[\n\
+             {\"language\":\"Python\",\"code\":0,\"comments\":1,\"blanks\":0},\n\
+             {\"language\":\"Rust\",\"code\":2,\"comments\":1,\"blanks\":1},\n\
+             {\"language\":null,\"code\":1,\"comments\":0,\"blanks\":0}\n\
+             ],\"total\":{\"code\":3,\"comments\":2,\"blanks\":1}}\n",
+        );
+
+    Ok(())
+}
+
+#[test]
+fn sigusr1_reports_progress_on_stderr() -> TestResult {
+    let mut rwc = process::Command::new(cargo_bin("rwc"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let mut stdin = rwc.stdin.take().unwrap();
+    stdin.write_all(b"one two\n")?;
+    // give rwc time to start up and count what it has been sent
+    std::thread::sleep(std::time::Duration::from_millis(500));
+
+    let kill = process::Command::new("kill")
+        .arg("-USR1")
+        .arg(rwc.id().to_string())
+        .status()?;
+    assert!(kill.success());
+    std::thread::sleep(std::time::Duration::from_millis(100));
+    stdin.write_all(b"three\n")?;
+    drop(stdin);
+
+    let output = rwc.wait_with_output()?;
+    output
+        .assert()
+        .success()
+        .stderr("      1       2       8\n")
+        .stdout("      2       3      14\n");
+
+    Ok(())
+}
+
+#[test]
+fn passthrough_flag_copies_stdin_and_reports_counts() -> TestResult {
+    let input = "one two\nthree\n";
+    let mut rwc = Command::cargo_bin("rwc")?;
+    rwc.arg("--passthrough")
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout(input)
+        .stderr("      2       3      14\n");
+
+    let dir = assert_fs::TempDir::new()?;
+    let report = dir.child("n.json");
+    let mut rwc = Command::cargo_bin("rwc")?;
+    rwc.args(["--passthrough", "-l", "--format=json", "--report"])
+        .arg(report.path())
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout(input)
+        .stderr("");
+    report.assert("{\"files\":[\n{\"file\":null,\"lines\":2}\n]}\n");
+
+    let mut rwc = Command::cargo_bin("rwc")?;
+    rwc.args(["--passthrough", "file.txt"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("cannot be used with"));
+
+    Ok(())
+}
+
+#[test]
+fn stats_and_histogram_flags_describe_line_lengths() -> TestResult {
+    let input = "hello\n\nab\r\nhello\n\tx\u{4f60}\n";
+    let mut rwc = Command::cargo_bin("rwc")?;
+    rwc.args(["-l", "--stats", "--histogram=2"])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout(
+            "5\n\
+             \x20 line lengths in bytes: min 0, max 5, mean 3.4, median 5, p99 5\n\
+             \x20 empty lines: 1\n\
+             \x20 longest at lines 1, 4, 5\n\
+             \x20 histogram of line lengths in bytes:\n\
+             \x20 0-1 1 ##############\n\
+             \x20 2-3 1 ##############\n\
+             \x20 4-5 3 ########################################\n",
+        );
+
+    let mut rwc = Command::cargo_bin("rwc")?;
+    rwc.args(["-l", "--stats", "--length-unit=columns", "--format=json"])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout(
+            "{\"files\":[\n{\"file\":null,\"lines\":5,\"line_lengths\":{\"unit\":\"columns\",\
+             \"lines\":5,\"empty\":1,\"min\":0,\"max\":11,\"mean\":4.60,\"median\":5,\
+             \"p99\":11,\"longest_at\":[5]}}\n]}\n",
+        );
+
+    let mut rwc = Command::cargo_bin("rwc")?;
+    rwc.args(["-l", "--stats", "--format=csv"])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout(
+            "file,lines,min_length,max_length,mean_length,median_length,p99_length,empty_lines\n\
+             ,5,0,5,3.40,5,5,1\n",
+        );
+
+    let mut rwc = Command::cargo_bin("rwc")?;
+    rwc.arg("--histogram=0")
+        .write_stdin(input)
+        .assert()
+        .code(1)
+        .stderr("rwc: histogram buckets must be at least 1 wide\n");
+
+    Ok(())
+}
+
+#[test]
+fn p_and_upper_f_flags_count_matches() -> TestResult {
+    let input = "ERROR x ERROR\nWARN aaaa\nERRORERROR\n";
+    let mut rwc = Command::cargo_bin("rwc")?;
+    rwc.args(["-l", "-p", "ERR+OR", "-F", "WARN", "-F", "aa"])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout("      3       4       1       2\n");
+
+    let mut rwc = Command::cargo_bin("rwc")?;
+    rwc.args(["--overlapping", "-p", "a+", "-F", "aa"])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout("      4       3\n");
+
+    // columns keep the order -p and -F were given in
+    let mut rwc = Command::cargo_bin("rwc")?;
+    rwc.args(["-F", "WARN", "-p", "ERR+OR", "-F", "aa", "-p", "a"])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout("      1       4       2       4\n");
+
+    let mut rwc = Command::cargo_bin("rwc")?;
+    rwc.args(["--format=csv", "-F", "WARN", "-p", "ERR+OR"])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout("file,WARN,ERR+OR\n,1,4\n");
+
+    let mut rwc = Command::cargo_bin("rwc")?;
+    rwc.args(["--format=json", "--total=always", "-F", "ERROR"])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout(
+            "{\"files\":[\n{\"file\":null,\"matches\":{\"ERROR\":4}}\n],\
+             \"total\":{\"matches\":{\"ERROR\":4}}}\n",
+        );
+
+    let mut rwc = Command::cargo_bin("rwc")?;
+    rwc.args(["--format=csv", "-F", "a,b"])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout("file,\"a,b\"\n,0\n");
+
+    let mut rwc = Command::cargo_bin("rwc")?;
+    rwc.args(["-p", "("])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("regex parse error"));
+
+    Ok(())
+}
+
+#[test]
+fn graphemes_flag_counts_user_perceived_characters() -> TestResult {
+    // a combining accent, an emoji with a skin tone and \r\n are each one
+    let input = "e\u{301} \u{1f44d}\u{1f3fd}\r\nab\n";
+    let mut rwc = Command::cargo_bin("rwc")?;
+    rwc.args(["-m", "--graphemes"])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout("     10       7\n");
+
+    let mut rwc = Command::cargo_bin("rwc")?;
+    rwc.args(["--graphemes", "--format=json"])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout("{\"files\":[\n{\"file\":null,\"graphemes\":7}\n]}\n");
+
+    Ok(())
+}
+
+#[test]
+fn graphemes_flag_counts_input_without_line_breaks() -> TestResult {
+    // clusters run across chunk edges, and one is too long to hold whole
+    let input = format!(
+        "{}x{}",
+        "e\u{301}\u{1f44d}\u{1f3fd} ".repeat(50_000),
+        "\u{301}".repeat(100_000)
+    );
+    let mut rwc = Command::cargo_bin("rwc")?;
+    rwc.args(["-m", "--graphemes"])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout(" 350001  150001\n");
+
+    Ok(())
+}
+
+#[test]
+fn top_flag_prints_the_most_frequent_words() -> TestResult {
+    let input = "The cat and the hat.\nthe CAT sat\nfoo_bar 9lives x1 the\n";
+    let mut rwc = Command::cargo_bin("rwc")?;
+    rwc.args(["--top=2"])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout("3 the\n1 9lives\n");
+
+    let dir = assert_fs::TempDir::new()?;
+    let stopwords = dir.child("stopwords.txt");
+    stopwords.write_str("AND\n")?;
+    let mut rwc = Command::cargo_bin("rwc")?;
+    rwc.args([
+        "-l",
+        "--top=4",
+        "--ignore-case",
+        "--tokens=identifier",
+        "--stopwords",
+    ])
+    .arg(stopwords.path())
+    .write_stdin(input)
+    .assert()
+    .success()
+    .stdout("3\n4 the\n2 cat\n1 foo_bar\n1 hat\n");
+
+    let mut rwc = Command::cargo_bin("rwc")?;
+    rwc.args(["--top=1", "--format=json"])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout("{\"files\":[\n{\"file\":null}\n],\"top_words\":[\n{\"word\":\"the\",\"count\":3}\n]}\n");
+
+    // a word making up half the input outlasts the rest in a small table
+    let mut rwc = Command::cargo_bin("rwc")?;
+    rwc.args(["--top=1", "--top-capacity=4"])
+        .write_stdin("a b a c a d a e a f a g\n")
+        .assert()
+        .success()
+        .stdout("5 a\n")
+        .stderr(predicate::str::contains("up to 1 too low"));
+
+    Ok(())
+}
+
+#[test]
+fn top_flag_streams_input_without_line_breaks() -> TestResult {
+    // words run across chunk edges, and one word is too long to keep whole
+    let input = format!(
+        "{}{} can't",
+        "can't stop ".repeat(100_000),
+        "x".repeat(100_000)
+    );
+    let expected = format!("100001 can't\n100000 stop\n     1 {}\n", "x".repeat(256));
+    for tokenizer in ["whitespace", "unicode"] {
+        let mut rwc = Command::cargo_bin("rwc")?;
+        rwc.args(["--top=3", "--tokens", tokenizer])
+            .write_stdin(input.clone())
+            .assert()
+            .success()
+            .stdout(expected.clone());
+    }
+
+    Ok(())
+}
+
+#[test]
+fn counter_counts_the_same_however_input_is_split() -> TestResult {
+    // words and characters of every length, cut off and stray continuation
+    // bytes, an overlong sequence, and what -L treats specially
+    let input: &[u8] = b"one  tw\xc3\xa9 \xe4\xbd\xa0\xe5\xa5\xbd\n\xf0\x9f\xa6\x80 cr\xe4\xbd\
+                         ab\r\n\x80\xff word\xe0\x80\x80\ttab \xc2\n\x0cend";
+    let mut counter = rwc::Counter::default();
+    counter.update(input);
+    let expected = counter.finish();
+    // as GNU wc -lwmcL counts it
+    let counts = rwc::Counts {
+        lines: 3,
+        words: 8,
+        chars: 35,
+        bytes: 51,
+        max_line_length: 13,
+    };
+    assert_eq!(expected, counts);
+
+    for first in 0..=input.len() {
+        for second in first..=input.len() {
+            let mut counter = rwc::Counter::new();
+            counter.update(&input[..first]);
+            counter.update(&input[first..second]);
+            counter.update(&input[second..]);
+            assert_eq!(
+                counter.finish(),
+                expected,
+                "split at {} and {}",
+                first,
+                second
+            );
+        }
+    }
+
+    Ok(())
+}